@@ -0,0 +1,150 @@
+// xdg-activation-v1 tokens for SNI Activate. Compositors with focus stealing
+// prevention (Hyprland, Sway) only raise a window that presents a token, so
+// we fetch one and hand it to the app before activating it.
+//
+// Tokens are requested on the session's Wayland connection (see wayland.rs)
+// with the serial of the last button press on a bar and the bar surface it
+// landed on, which strict compositors insist on. iced_layershell doesn't
+// expose either, so we keep our own wl_pointer on the seat to see them.
+
+use std::sync::{Mutex, OnceLock};
+
+use tokio::sync::oneshot;
+use wayland_client::globals::GlobalList;
+use wayland_client::protocol::wl_pointer::{self, WlPointer};
+use wayland_client::protocol::wl_seat::{self, WlSeat};
+use wayland_client::protocol::wl_surface::WlSurface;
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle, WEnum};
+use wayland_protocols::xdg::activation::v1::client::xdg_activation_token_v1::{self, XdgActivationTokenV1};
+use wayland_protocols::xdg::activation::v1::client::xdg_activation_v1::{self, XdgActivationV1};
+
+use crate::wayland::WaylandState;
+
+// Set by bind when the compositor has xdg-activation
+static ACTIVATION: OnceLock<Activation> = OnceLock::new();
+
+// Written by the Wayland dispatch thread, read when a token is requested
+static INPUT: Mutex<Input> = Mutex::new(Input { seat: None, pointer: None, serial: None, surface: None });
+
+struct Activation {
+    connection: Connection,
+    manager: XdgActivationV1,
+    handle: QueueHandle<WaylandState>,
+}
+
+struct Input {
+    seat: Option<WlSeat>,
+    pointer: Option<WlPointer>,
+    serial: Option<u32>,        // Last button press
+    surface: Option<WlSurface>, // Last surface the pointer entered
+}
+
+// Where a token request waits for its Done; None once answered
+type TokenReply = Mutex<Option<oneshot::Sender<String>>>;
+
+// Binds xdg-activation and the first seat on the session connection
+pub fn bind(globals: &GlobalList, handle: &QueueHandle<WaylandState>, connection: &Connection) {
+    let Ok(manager) = globals.bind::<XdgActivationV1, _, _>(handle, 1..=1, ()) else {
+        tracing::debug!("Compositor has no xdg-activation, apps are activated without tokens");
+        return;
+    };
+    INPUT.lock().unwrap().seat = globals.bind::<WlSeat, _, _>(handle, 1..=5, ()).ok();
+    let _ = ACTIVATION.set(Activation { connection: connection.clone(), manager, handle: handle.clone() });
+}
+
+// Resolves once the compositor answers; None without xdg-activation. Callers
+// put a timeout on it; a late answer is then dropped without a thread or a
+// connection left behind.
+pub async fn request_token() -> Option<String> {
+    let activation = ACTIVATION.get()?;
+    let (tx, rx) = oneshot::channel();
+    let request = activation.manager.get_activation_token(&activation.handle, Mutex::new(Some(tx)));
+    {
+        let input = INPUT.lock().unwrap();
+        if let (Some(seat), Some(serial)) = (&input.seat, input.serial) {
+            request.set_serial(serial, seat);
+        }
+        if let Some(surface) = &input.surface {
+            request.set_surface(surface);
+        }
+    }
+    request.set_app_id("vibebar".to_string());
+    request.commit();
+    // The dispatch thread only flushes when it next wakes up
+    activation.connection.flush().ok()?;
+    rx.await.ok()
+}
+
+// xdg_activation_v1 sends no events
+impl Dispatch<XdgActivationV1, ()> for WaylandState {
+    fn event(
+        _: &mut Self,
+        _: &XdgActivationV1,
+        _: xdg_activation_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<XdgActivationTokenV1, TokenReply> for WaylandState {
+    fn event(
+        _: &mut Self,
+        request: &XdgActivationTokenV1,
+        event: xdg_activation_token_v1::Event,
+        reply: &TokenReply,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_activation_token_v1::Event::Done { token } = event {
+            if let Some(tx) = reply.lock().unwrap().take() {
+                let _ = tx.send(token);
+            }
+            request.destroy();
+        }
+    }
+}
+
+impl Dispatch<WlSeat, ()> for WaylandState {
+    fn event(
+        _: &mut Self,
+        seat: &WlSeat,
+        event: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        handle: &QueueHandle<Self>,
+    ) {
+        if let wl_seat::Event::Capabilities { capabilities: WEnum::Value(capabilities) } = event {
+            let mut input = INPUT.lock().unwrap();
+            let has_pointer = capabilities.contains(wl_seat::Capability::Pointer);
+            if has_pointer && input.pointer.is_none() {
+                input.pointer = Some(seat.get_pointer(handle, ()));
+            } else if !has_pointer
+                && let Some(pointer) = input.pointer.take()
+                && pointer.version() >= 3
+            {
+                pointer.release();
+            }
+        }
+    }
+}
+
+impl Dispatch<WlPointer, ()> for WaylandState {
+    fn event(
+        _: &mut Self,
+        _: &WlPointer,
+        event: wl_pointer::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_pointer::Event::Enter { surface, .. } => INPUT.lock().unwrap().surface = Some(surface),
+            wl_pointer::Event::Button { serial, state: WEnum::Value(wl_pointer::ButtonState::Pressed), .. } => {
+                INPUT.lock().unwrap().serial = Some(serial);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,662 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use iced::Color;
+use serde::{Deserialize, Deserializer};
+use system_tray::item::Category;
+
+// Defaults for the stock bar
+const BAR_HEIGHT: u32 = 30;
+const ICON_SIZE: f32 = 22.0;
+const CONTAINER_SIZE: f32 = 26.0;
+const EDGE_MARGIN: f32 = 10.0;
+const TRAY_SPACING: f32 = 4.0;
+
+// Keys the density preset fills in when the file doesn't set them
+const SIZE_KEYS: [&str; 4] = ["bar_height", "icon_size", "container_size", "tray_spacing"];
+
+// User-tunable settings, read from $XDG_CONFIG_HOME/vibebar/config.toml.
+// Every field is optional in the file; defaults reproduce the stock bar.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Layout
+    pub position: BarPosition,
+    pub orientation: BarOrientation,
+    pub bar_height: u32,     // Bar thickness (width when vertical); also the exclusive zone
+    pub reserve_space: bool, // false floats the bar on the overlay layer without an exclusive zone
+    pub icon_size: f32,      // Rendered icon edge length
+    pub container_size: f32, // Hover/click target around each icon
+    #[serde(alias = "margin_right")]
+    pub edge_margin: f32,    // Gap between the outermost modules and the screen edges
+    pub tray_spacing: f32,   // Space between neighbouring tray icons
+    pub density: Density,    // Preset for whichever of the four sizes above the file leaves unset
+    #[serde(skip)]
+    explicit_sizes: Vec<&'static str>, // Those the file does set
+    pub icon_backing: IconBacking,
+    #[serde(rename = "outputs")]
+    outputs_key: OutputsKey, // Either form of the key; load sorts it into the two fields below
+    #[serde(skip)]
+    pub outputs: Vec<String>, // wl_output names to show a bar on, one bar each; empty means every output
+    #[serde(rename = "output")]
+    pub output_overrides: HashMap<String, OutputOverride>, // [outputs."DP-1"] (or [output."DP-1"]) sections, by wl_output name
+    pub icon_theme: Option<String>, // Freedesktop theme name; unset follows GTK_ICON_THEME / GNOME settings
+    pub namespace: String, // Layer-shell namespace; give each instance its own to run several bars
+
+    // Colors
+    pub theme: ThemeMode,
+    #[serde(flatten)]
+    pub colors: PaletteOverrides, // Keys sit at the top level of the file and apply to either theme
+    pub background_opacity: f32,  // 0.0-1.0, multiplied into bar_background's alpha
+    pub bar_corner_radius: f32,   // Rounds the bar's corners; mostly useful with reserve_space = false
+    pub bar_border: BarBorder,
+    pub popup_opacity: f32, // 0.0-1.0, multiplied into menu_background's alpha for menus and tooltips
+    pub popup_corner_radius: Option<f32>, // Unset keeps the built-in rounding; 0 gives square popups
+
+    // Clock
+    pub show_clock: bool,
+    pub clock_position: BarSegment,
+    pub clock_format: String, // strftime, e.g. "%a %d %b %H:%M"
+
+    // Empty tray hint
+    #[serde(alias = "show_empty_placeholder")]
+    pub show_empty_message: bool,     // Render a hint in the tray area when there are no items
+    pub empty_message: String,        // Hint when connected to the tray but nothing is registered
+    pub disconnected_message: String, // Hint while the tray client is not connected
+
+    // Behavior
+    #[serde(rename = "hover_grace_ms", deserialize_with = "de_millis")]
+    pub hover_grace: Duration, // Keep the highlight if the pointer re-enters within this window
+    #[serde(rename = "tray_batch_ms", deserialize_with = "de_millis")]
+    pub tray_batch: Duration, // Tray events this close together are applied in one update and redraw
+    pub wait_for_initial_items: bool, // Hide the tray row until startup items are in, to avoid pop-in
+    pub reduce_motion: bool, // Skip animations: the icon fade-in and popup transitions
+    pub dim_passive_items: bool, // Draw items with Passive status at reduced opacity
+    pub notify_on_tray_loss: bool, // Desktop notification when a live tray connection drops
+    pub max_visible_icons: Option<usize>, // Icons past this many go behind a "+N" indicator
+    pub overflow_action: OverflowAction, // What clicking the "+N" overflow indicator does
+    pub on_bar_click_command: Option<String>, // Shell command for left clicks on empty bar space
+    pub click_command: Option<String>, // Shell command run on every tray icon click, e.g. a click sound
+    pub single_click_action: SingleClickAction, // Double clicks always activate
+    pub left_click: LeftClickAction, // What "activate" means for left clicks
+    pub enable_middle_click: bool, // false ignores middle clicks on tray icons entirely
+    pub launch_on_activate_failure: bool, // Start the app's .desktop entry when Activate and ContextMenu both fail
+    pub middle_click_action: MiddleClickAction,
+    pub buttons: ButtonActions, // Wins over single_click_action, left_click, middle_click_action
+    pub overrides: HashMap<String, ButtonActions>, // Per-app click actions, keyed by SNI id
+    pub tooltip_overrides: Vec<TooltipOverride>, // First match wins
+    pub show_tray: bool,
+    pub tray_position: BarSegment,
+    pub sort: IconSort,
+    pub group_by_category: bool, // Group icons by SNI Category, with a separator between groups
+    pub category_order: Vec<Category>, // Group order; unlisted categories go last
+    pub icon_order: Vec<String>, // Globs pinning icons in this order; "..." marks the unmatched ones
+    pub collapsible: bool,        // Fold icons not in always_shown behind a chevron
+    pub always_shown: Vec<String>, // Globs on SNI id, title or bus name kept visible when collapsed
+    pub blacklist: Vec<String>,    // Globs on SNI id never shown
+    pub whitelist: Vec<String>,    // Globs on SNI id; when non-empty, only these are shown
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            position: BarPosition::default(),
+            orientation: BarOrientation::default(),
+            bar_height: BAR_HEIGHT,
+            reserve_space: true,
+            icon_size: ICON_SIZE,
+            container_size: CONTAINER_SIZE,
+            edge_margin: EDGE_MARGIN,
+            tray_spacing: TRAY_SPACING,
+            density: Density::default(),
+            explicit_sizes: Vec::new(),
+            icon_backing: IconBacking::default(),
+            outputs_key: OutputsKey::default(),
+            outputs: Vec::new(),
+            output_overrides: HashMap::new(),
+            icon_theme: None,
+            namespace: "vibebar".to_string(),
+            theme: ThemeMode::default(),
+            colors: PaletteOverrides::default(),
+            background_opacity: 1.0,
+            bar_corner_radius: 0.0,
+            bar_border: BarBorder::default(),
+            popup_opacity: 1.0,
+            popup_corner_radius: None,
+            show_clock: true,
+            clock_position: BarSegment::Right,
+            clock_format: "%H:%M".to_string(),
+            show_empty_message: false,
+            empty_message: "No tray items".to_string(),
+            disconnected_message: "Tray not connected".to_string(),
+            hover_grace: Duration::ZERO,
+            tray_batch: Duration::from_millis(16),
+            wait_for_initial_items: false,
+            reduce_motion: false,
+            dim_passive_items: false,
+            notify_on_tray_loss: false,
+            max_visible_icons: None,
+            overflow_action: OverflowAction::default(),
+            on_bar_click_command: None,
+            click_command: None,
+            single_click_action: SingleClickAction::default(),
+            left_click: LeftClickAction::default(),
+            enable_middle_click: true,
+            launch_on_activate_failure: false,
+            middle_click_action: MiddleClickAction::default(),
+            buttons: ButtonActions::default(),
+            overrides: HashMap::new(),
+            tooltip_overrides: Vec::new(),
+            show_tray: true,
+            tray_position: BarSegment::Right,
+            sort: IconSort::default(),
+            group_by_category: false,
+            category_order: vec![
+                Category::ApplicationStatus,
+                Category::Communications,
+                Category::SystemServices,
+                Category::Hardware,
+            ],
+            icon_order: Vec::new(),
+            collapsible: false,
+            always_shown: Vec::new(),
+            blacklist: Vec::new(),
+            whitelist: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // Edge the bar actually sits on. A vertical bar turns top/bottom into
+    // left/right; an explicit left/right position implies a vertical bar.
+    pub fn edge(&self) -> BarPosition {
+        match (self.orientation, self.position) {
+            (BarOrientation::Vertical, BarPosition::Top) => BarPosition::Left,
+            (BarOrientation::Vertical, BarPosition::Bottom) => BarPosition::Right,
+            (_, position) => position,
+        }
+    }
+
+    // Fills the sizes the file left unset from the density preset. Run after
+    // --compact and before for_output, whose sections win over both.
+    pub fn apply_density(&mut self) {
+        let preset = self.density.preset();
+        let unset = |key: &str| !self.explicit_sizes.contains(&key);
+        if unset("bar_height") {
+            self.bar_height = preset.bar_height;
+        }
+        if unset("icon_size") {
+            self.icon_size = preset.icon_size;
+        }
+        if unset("container_size") {
+            self.container_size = preset.container_size;
+        }
+        if unset("tray_spacing") {
+            self.tray_spacing = preset.tray_spacing;
+        }
+    }
+
+    // The config for the bar on `output`: this one with that output's
+    // [outputs."NAME"] section applied
+    pub fn for_output(&self, output: &str) -> Config {
+        let mut config = self.clone();
+        if let Some(section) = self.output_overrides.get(output) {
+            config.bar_height = section.bar_height.unwrap_or(config.bar_height);
+            config.icon_size = section.icon_size.unwrap_or(config.icon_size);
+            config.container_size = section.container_size.unwrap_or(config.container_size);
+            config.show_clock = section.show_clock.unwrap_or(config.show_clock);
+            config.show_tray = section.show_tray.unwrap_or(config.show_tray);
+        }
+        config
+    }
+
+    pub fn is_vertical(&self) -> bool {
+        matches!(self.edge(), BarPosition::Left | BarPosition::Right)
+    }
+
+    // Colors to draw with, given whether the desktop prefers a dark scheme
+    // (only consulted when `theme` is auto)
+    pub fn palette(&self, system_dark: bool) -> Palette {
+        let dark = match self.theme {
+            ThemeMode::Auto => system_dark,
+            ThemeMode::Dark => true,
+            ThemeMode::Light => false,
+        };
+        let base = if dark { Palette::DARK } else { Palette::LIGHT };
+        self.colors.apply(base)
+    }
+}
+
+// Every color the bar draws with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub bar_background: Color,
+    pub menu_background: Color, // Context menus and tooltips
+    pub menu_border: Color,
+    pub text: Color,             // Clock, menu entries, tooltips
+    pub text_disabled: Color,    // Disabled menu entries and the empty tray hint
+    pub hover_background: Color, // Behind a hovered tray icon
+    pub hover_border: Color,
+    pub menu_hover: Color,     // Behind a hovered or keyboard-highlighted menu entry
+    pub attention: Color,      // Border of icons whose status is NeedsAttention
+    pub symbolic_color: Color, // Fill for monochrome "-symbolic" icons
+}
+
+impl Palette {
+    // "Dark Prism", the stock look
+    pub const DARK: Palette = Palette {
+        bar_background: Color::from_rgb(9.0 / 255.0, 9.0 / 255.0, 11.0 / 255.0),
+        menu_background: Color::from_rgb(24.0 / 255.0, 24.0 / 255.0, 27.0 / 255.0),
+        menu_border: Color::from_rgba(1.0, 1.0, 1.0, 0.1),
+        text: Color::from_rgb(244.0 / 255.0, 244.0 / 255.0, 245.0 / 255.0),
+        text_disabled: Color::from_rgb(113.0 / 255.0, 113.0 / 255.0, 122.0 / 255.0),
+        hover_background: Color::from_rgba(1.0, 1.0, 1.0, 0.20),
+        hover_border: Color::from_rgba(1.0, 1.0, 1.0, 0.50),
+        menu_hover: Color::from_rgba(1.0, 1.0, 1.0, 0.10),
+        attention: Color::from_rgb(245.0 / 255.0, 158.0 / 255.0, 11.0 / 255.0),
+        symbolic_color: Color::from_rgb(244.0 / 255.0, 244.0 / 255.0, 245.0 / 255.0),
+    };
+
+    // The same zinc scale, inverted
+    pub const LIGHT: Palette = Palette {
+        bar_background: Color::from_rgb(250.0 / 255.0, 250.0 / 255.0, 250.0 / 255.0),
+        menu_background: Color::from_rgb(1.0, 1.0, 1.0),
+        menu_border: Color::from_rgba(0.0, 0.0, 0.0, 0.1),
+        text: Color::from_rgb(24.0 / 255.0, 24.0 / 255.0, 27.0 / 255.0),
+        text_disabled: Color::from_rgb(161.0 / 255.0, 161.0 / 255.0, 170.0 / 255.0),
+        hover_background: Color::from_rgba(0.0, 0.0, 0.0, 0.08),
+        hover_border: Color::from_rgba(0.0, 0.0, 0.0, 0.30),
+        menu_hover: Color::from_rgba(0.0, 0.0, 0.0, 0.06),
+        attention: Color::from_rgb(217.0 / 255.0, 119.0 / 255.0, 6.0 / 255.0),
+        symbolic_color: Color::from_rgb(24.0 / 255.0, 24.0 / 255.0, 27.0 / 255.0),
+    };
+
+    pub fn is_dark(&self) -> bool {
+        let bg = self.bar_background;
+        // Rec. 709 luma
+        0.2126 * bg.r + 0.7152 * bg.g + 0.0722 * bg.b < 0.5
+    }
+
+    // Every color with its alpha scaled, for fading whole views in and out
+    pub fn faded(self, alpha: f32) -> Palette {
+        let fade = |c: Color| Color { a: c.a * alpha, ..c };
+        Palette {
+            bar_background: fade(self.bar_background),
+            menu_background: fade(self.menu_background),
+            menu_border: fade(self.menu_border),
+            text: fade(self.text),
+            text_disabled: fade(self.text_disabled),
+            hover_background: fade(self.hover_background),
+            hover_border: fade(self.hover_border),
+            menu_hover: fade(self.menu_hover),
+            attention: fade(self.attention),
+            symbolic_color: fade(self.symbolic_color),
+        }
+    }
+}
+
+// Colors set in the file, as "#rrggbb" or "#rrggbbaa"; unset ones come from the theme
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct PaletteOverrides {
+    #[serde(deserialize_with = "de_opt_color")]
+    pub bar_background: Option<Color>,
+    #[serde(deserialize_with = "de_opt_color")]
+    pub menu_background: Option<Color>,
+    #[serde(deserialize_with = "de_opt_color")]
+    pub menu_border: Option<Color>,
+    #[serde(deserialize_with = "de_opt_color")]
+    pub text: Option<Color>,
+    #[serde(deserialize_with = "de_opt_color")]
+    pub text_disabled: Option<Color>,
+    #[serde(deserialize_with = "de_opt_color")]
+    pub hover_background: Option<Color>,
+    #[serde(deserialize_with = "de_opt_color")]
+    pub hover_border: Option<Color>,
+    #[serde(deserialize_with = "de_opt_color")]
+    pub menu_hover: Option<Color>,
+    #[serde(deserialize_with = "de_opt_color")]
+    pub attention: Option<Color>,
+    #[serde(deserialize_with = "de_opt_color")]
+    pub symbolic_color: Option<Color>,
+}
+
+impl PaletteOverrides {
+    fn apply(&self, base: Palette) -> Palette {
+        Palette {
+            bar_background: self.bar_background.unwrap_or(base.bar_background),
+            menu_background: self.menu_background.unwrap_or(base.menu_background),
+            menu_border: self.menu_border.unwrap_or(base.menu_border),
+            text: self.text.unwrap_or(base.text),
+            text_disabled: self.text_disabled.unwrap_or(base.text_disabled),
+            hover_background: self.hover_background.unwrap_or(base.hover_background),
+            hover_border: self.hover_border.unwrap_or(base.hover_border),
+            menu_hover: self.menu_hover.unwrap_or(base.menu_hover),
+            attention: self.attention.unwrap_or(base.attention),
+            symbolic_color: self.symbolic_color.unwrap_or(base.symbolic_color),
+        }
+    }
+}
+
+// The outputs key holds either the outputs to show a bar on or, as
+// [outputs."NAME"] tables, per-output sections. TOML can't have both under
+// one key, so sections can also be written [output."NAME"].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum OutputsKey {
+    Names(Vec<String>),
+    Sections(HashMap<String, OutputOverride>),
+}
+
+impl Default for OutputsKey {
+    fn default() -> Self {
+        OutputsKey::Names(Vec::new())
+    }
+}
+
+// Settings an [outputs."NAME"] section can change; unset ones keep the
+// top-level value
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OutputOverride {
+    pub bar_height: Option<u32>,
+    pub icon_size: Option<f32>,
+    pub container_size: Option<f32>,
+    pub show_clock: Option<bool>,
+    pub show_tray: Option<bool>,
+}
+
+// Outline around the bar; width 0 (the default) draws none
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct BarBorder {
+    pub width: f32,
+    #[serde(deserialize_with = "de_opt_color")]
+    pub color: Option<Color>, // Unset uses the theme's menu_border
+}
+
+// Which palette to start from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeMode {
+    #[default]
+    Auto, // Follow the desktop's color-scheme setting; dark when it has none
+    Dark,
+    Light,
+}
+
+// Screen edge the bar is anchored to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BarPosition {
+    #[default]
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl std::str::FromStr for BarPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "top" => Ok(BarPosition::Top),
+            "bottom" => Ok(BarPosition::Bottom),
+            "left" => Ok(BarPosition::Left),
+            "right" => Ok(BarPosition::Right),
+            other => Err(format!(
+                "invalid bar position '{other}', expected 'top', 'bottom', 'left' or 'right'"
+            )),
+        }
+    }
+}
+
+// Horizontal bars span the top or bottom edge; vertical ones the left or right
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BarOrientation {
+    #[default]
+    Horizontal,
+    Vertical,
+}
+
+impl std::str::FromStr for BarOrientation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "horizontal" => Ok(BarOrientation::Horizontal),
+            "vertical" => Ok(BarOrientation::Vertical),
+            other => Err(format!("invalid orientation '{other}', expected 'horizontal' or 'vertical'")),
+        }
+    }
+}
+
+// User-supplied tooltip text for items matched by SNI id or title
+#[derive(Debug, Clone, Deserialize)]
+pub struct TooltipOverride {
+    pub pattern: String, // Glob ('*', '?') matched against the item's id, then its title
+    pub text: String,    // Template; "{title}" and "{id}" are interpolated
+    #[serde(default)]
+    pub append: bool, // Add below the app's own tooltip instead of replacing it
+}
+
+// Behavior of the overflow indicator shown when icons don't all fit.
+// In TOML: "popup", "cycle", or { command = "..." }
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowAction {
+    #[default]
+    Popup,           // Open a popup listing the hidden icons
+    Cycle,           // Rotate the hidden icons into the visible slots
+    Command(String), // Run a shell command
+}
+
+// Part of the bar a module sits in; on a vertical bar left is the top and
+// right the bottom. Modules sharing a segment go clock first, then tray.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BarSegment {
+    Left,
+    Center,
+    #[default]
+    Right,
+}
+
+// Size preset for the bar and its icons
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Density {
+    #[default]
+    Comfortable, // The stock sizes
+    Compact,     // Tighter bar, smaller icons and gaps
+}
+
+pub struct SizePreset {
+    pub bar_height: u32,
+    pub icon_size: f32,
+    pub container_size: f32,
+    pub tray_spacing: f32,
+}
+
+impl Density {
+    pub fn preset(self) -> SizePreset {
+        match self {
+            Density::Comfortable => SizePreset {
+                bar_height: BAR_HEIGHT,
+                icon_size: ICON_SIZE,
+                container_size: CONTAINER_SIZE,
+                tray_spacing: TRAY_SPACING,
+            },
+            Density::Compact => SizePreset {
+                bar_height: 24,
+                icon_size: 16.0,
+                container_size: 20.0,
+                tray_spacing: 2.0,
+            },
+        }
+    }
+}
+
+// Background behind tray icons; hovered icons and those needing attention
+// also get a border, whatever this says
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IconBacking {
+    None,   // Never, not even on hover
+    #[default]
+    Hover,  // Only behind the hovered or keyboard-focused icon
+    Always, // A faint one behind every icon, stronger on hover
+}
+
+// Order of tray icons on the bar
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IconSort {
+    #[default]
+    Insertion,    // As items first appeared
+    Alphabetical, // By SNI id
+    Title,        // By title, falling back to the id
+}
+
+// What a single left click on a tray icon does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SingleClickAction {
+    #[default]
+    Activate, // SNI Activate, like a double click
+    Menu,     // Open the context menu
+    None,     // Only double clicks do anything
+}
+
+// SNI call behind a left click that activates (see SingleClickAction)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LeftClickAction {
+    #[default]
+    Activate, // Activate, or the app's ContextMenu for menu-only items or if Activate fails
+    Menu,     // Always the app's ContextMenu
+}
+
+// What middle-clicking a tray icon does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MiddleClickAction {
+    #[default]
+    SecondaryActivate, // SNI SecondaryActivate, handled by the app
+    Quit,              // Trigger the app's Quit/Exit/Close menu entry, if it has one
+}
+
+// What each mouse button does on a tray icon. Unset buttons fall back to
+// the next table: per-app overrides, then `buttons`, then the older
+// single_click_action / left_click / middle_click_action keys.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct ButtonActions {
+    pub left: Option<ClickAction>,
+    pub right: Option<ClickAction>,
+    pub middle: Option<ClickAction>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClickAction {
+    Activate,    // SNI Activate; the app's ContextMenu for menu-only items or if Activate fails
+    ContextMenu, // The app's own ContextMenu, not vibebar's popup
+    SecondaryActivate,
+    Menu, // vibebar's popup, built from the app's dbusmenu
+    Quit, // Trigger the app's Quit/Exit/Close menu entry, if it has one
+    None,
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("vibebar").join("config.toml"))
+}
+
+// A missing file means defaults; a broken one is reported and also means
+// defaults. Given an explicit path (--config), a missing file is reported too.
+pub fn load(explicit_path: Option<PathBuf>) -> Config {
+    let explicit = explicit_path.is_some();
+    let Some(path) = explicit_path.or_else(config_path) else {
+        return Config::default();
+    };
+
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => return Config::default(),
+        Err(e) => {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to read config; using defaults");
+            return Config::default();
+        }
+    };
+
+    parse(&contents).unwrap_or_else(|e| {
+        tracing::warn!(path = %path.display(), error = %e, "Invalid config; using defaults");
+        Config::default()
+    })
+}
+
+// A config file's contents, with the keys serde can't sort out alone resolved
+fn parse(contents: &str) -> Result<Config, toml::de::Error> {
+    let mut config: Config = toml::from_str(contents)?;
+    // Parsed a second time to tell set keys from defaulted ones
+    if let Ok(table) = contents.parse::<toml::Table>() {
+        config.explicit_sizes = SIZE_KEYS.into_iter().filter(|key| table.contains_key(*key)).collect();
+    }
+    match std::mem::take(&mut config.outputs_key) {
+        OutputsKey::Names(names) => config.outputs = names,
+        OutputsKey::Sections(sections) => config.output_overrides.extend(sections),
+    }
+    Ok(config)
+}
+
+// Parses "#rrggbb" or "#rrggbbaa" (leading '#' optional)
+pub fn parse_hex_color(s: &str) -> Result<Color, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if let Some(c) = hex.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!("invalid color '{s}': '{c}' is not a hex digit, expected #rrggbb or #rrggbbaa"));
+    }
+    if !matches!(hex.len(), 6 | 8) {
+        return Err(format!(
+            "invalid color '{s}': {} hex digits, expected 6 (#rrggbb) or 8 (#rrggbbaa)",
+            hex.len()
+        ));
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(0);
+    let alpha = if hex.len() == 8 { channel(6) as f32 / 255.0 } else { 1.0 };
+    Ok(Color::from_rgba8(channel(0), channel(2), channel(4), alpha))
+}
+
+fn de_opt_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Color>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_hex_color(&s).map(Some).map_err(serde::de::Error::custom)
+}
+
+fn de_millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outputs_key_takes_a_list_or_sections() {
+        let config = parse(r#"outputs = ["DP-1", "eDP-1"]"#).unwrap();
+        assert_eq!(config.outputs, ["DP-1", "eDP-1"]);
+        assert!(config.output_overrides.is_empty());
+
+        let config = parse("[outputs.\"DP-1\"]\nicon_size = 32.0\n[output.eDP-1]\nshow_clock = false").unwrap();
+        assert!(config.outputs.is_empty());
+        assert_eq!(config.for_output("DP-1").icon_size, 32.0);
+        assert!(!config.for_output("eDP-1").show_clock);
+        assert_eq!(config.for_output("HDMI-A-1").icon_size, config.icon_size);
+    }
+}
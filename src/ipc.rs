@@ -0,0 +1,135 @@
+// Command socket for scripting tray items. One command per line, answered
+// with "ok" or "error: <reason>":
+//
+//   activate <id>            SNI Activate (the app's menu for menu-only items)
+//   context-menu <id>        the app's own context menu
+//   secondary-activate <id>  SNI SecondaryActivate
+//
+// `<id>` is the SNI id shown by --list-items. Commands name the SNI call
+// directly, so button mappings and per-app overrides don't apply.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use system_tray::data::BaseMap;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+
+use crate::{ActivateRequest, SniCall, lock_items};
+
+// $XDG_RUNTIME_DIR/<namespace>.sock, so bars with their own namespace don't collide
+pub fn socket_path(namespace: &str) -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+    Some(PathBuf::from(dir).join(format!("{namespace}.sock")))
+}
+
+// Serves the socket until `tx`'s receiver goes away, i.e. the tray connection
+// it belongs to is dropped. The next connection binds the path afresh.
+pub async fn serve(path: PathBuf, items: Arc<Mutex<BaseMap>>, tx: mpsc::UnboundedSender<ActivateRequest>) {
+    let Some(listener) = bind(&path) else {
+        return;
+    };
+    tracing::debug!(path = %path.display(), "Listening for commands");
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, items.clone(), tx.clone()));
+                }
+                Err(e) => tracing::warn!(error = %e, "Failed to accept command connection"),
+            },
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+fn bind(path: &Path) -> Option<UnixListener> {
+    // A socket nobody answers on is left over from an earlier run or tray
+    // connection. One that answers belongs to another instance on the same
+    // namespace, which keeps it.
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => {
+            tracing::warn!(
+                path = %path.display(),
+                "Another instance is serving commands on this socket; give this one its own --namespace"
+            );
+            return None;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            let _ = std::fs::remove_file(path);
+        }
+        Err(_) => {}
+    }
+    match UnixListener::bind(path) {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to bind command socket");
+            None
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    items: Arc<Mutex<BaseMap>>,
+    tx: mpsc::UnboundedSender<ActivateRequest>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let reply = match run_command(line.trim(), &items, &tx) {
+            Ok(()) => "ok\n".to_string(),
+            Err(e) => format!("error: {e}\n"),
+        };
+        if writer.write_all(reply.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn run_command(
+    line: &str,
+    items: &Mutex<BaseMap>,
+    tx: &mpsc::UnboundedSender<ActivateRequest>,
+) -> Result<(), String> {
+    let (command, id) = line.split_once(char::is_whitespace).ok_or("expected <command> <id>")?;
+    let call = match command {
+        "activate" => SniCall::Activate,
+        "context-menu" => SniCall::ContextMenu,
+        "secondary-activate" => SniCall::SecondaryActivate,
+        _ => return Err(format!("unknown command {command:?}")),
+    };
+
+    let id = id.trim();
+    let address = lock_items(items)
+        .iter()
+        .find(|(_, (item, _))| item.id == id)
+        .map(|(address, _)| address.clone())
+        .ok_or_else(|| format!("no tray item with id {id:?}"))?;
+
+    // There is no pointer position to report; apps place their menus themselves
+    tx.send((address, call, 0, 0)).map_err(|_| "tray disconnected".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn live_sockets_are_kept_and_stale_ones_replaced() {
+        let path = std::env::temp_dir().join(format!("vibebar-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let running = bind(&path).unwrap();
+        assert!(bind(&path).is_none());
+
+        // Gone without cleaning up: the file stays, nobody listens
+        drop(running);
+        assert!(path.exists());
+        assert!(bind(&path).is_some());
+        let _ = std::fs::remove_file(&path);
+    }
+}
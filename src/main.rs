@@ -1,41 +1,162 @@
-use std::collections::HashMap;
+mod activation;
+mod config;
+mod ipc;
+#[cfg(test)]
+mod tests;
+mod wayland;
+mod xpm;
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::PathBuf;
-use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
 
-use iced::widget::{Space, container, image, mouse_area, row, svg};
+use iced::widget::{Space, container, image, mouse_area, row, scrollable, stack, svg};
 use iced::{Background, Border, Color, Element, Length, Subscription, Theme};
 
+use clap::Parser;
 use iced::window;
 use iced_layershell::actions::{IcedNewPopupSettings, LayershellCustomAction, LayershellCustomActionWithId};
-use iced_layershell::reexport::Anchor;
+use iced_layershell::reexport::{Anchor, KeyboardInteractivity, Layer, NewLayerShellSettings, OutputOption};
 use iced_layershell::settings::{LayerShellSettings, Settings, StartMode};
 use iced_layershell::daemon;
 
 use system_tray::client::{Client, Event, UpdateEvent};
-use system_tray::item::IconPixmap;
+use system_tray::data::BaseMap;
+use system_tray::item::{Category, IconPixmap, Status, StatusNotifierItem, Tooltip};
+use system_tray::menu::{MenuItem, MenuType, ToggleState, ToggleType, TrayMenu};
 use tokio::sync::mpsc;
 use zbus::Connection;
 
-// Channel for sending activation requests to the subscription (address, click_type, x, y)
-static ACTIVATE_TX: OnceLock<mpsc::UnboundedSender<(String, ClickType, i32, i32)>> = OnceLock::new();
+use config::{
+    BarOrientation, BarPosition, ClickAction, Config, Density, IconBacking, IconSort, LeftClickAction, MiddleClickAction, OverflowAction,
+    Palette, SingleClickAction, ThemeMode,
+};
 
-// Design constants
-const BAR_BG: Color = Color::from_rgb(9.0 / 255.0, 9.0 / 255.0, 11.0 / 255.0);
-const ICON_SIZE: f32 = 22.0;
-const CONTAINER_SIZE: f32 = 26.0;
+// Activation request sent to the subscription (address, call, x, y)
+type ActivateRequest = (String, SniCall, i32, i32);
 
-#[derive(Debug, Clone)]
+// Channel for sending activation requests to the subscription. Replaced on
+// every (re)connect so clicks always reach the live receiver.
+static ACTIVATE_TX: Mutex<Option<mpsc::UnboundedSender<ActivateRequest>>> = Mutex::new(None);
+
+// Resolved named icons keyed by (icon_name, icon_theme_path), including
+// misses, so repeated updates don't hit the filesystem again
+type IconCacheKey = (String, Option<String>);
+static ICON_CACHE: LazyLock<Mutex<HashMap<IconCacheKey, Option<IconHandle>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Session bus connection for our own SNI/dbusmenu calls, opened on first use
+static SESSION_BUS: tokio::sync::OnceCell<Connection> = tokio::sync::OnceCell::const_new();
+
+// Full SNI address (bus name plus object path) per tray item bus name,
+// filled on Add and dropped on Remove
+static SNI_ADDRESSES: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Pixel size requested from freedesktop lookups and targeted when choosing
+// among an item's pixmaps: the icon size in physical pixels, so rasters match
+// the output's scale. Reset on scale changes.
+static ICON_LOOKUP_SIZE: AtomicU16 = AtomicU16::new(128);
+
+// config.launch_on_activate_failure, for the tray subscription; set at
+// startup and on reload
+static LAUNCH_ON_ACTIVATE_FAILURE: AtomicBool = AtomicBool::new(false);
+
+// config.tray_batch in milliseconds, for the tray subscription; set at
+// startup and on reload
+static TRAY_BATCH_MS: AtomicU64 = AtomicU64::new(16);
+
+// Icon theme passed to freedesktop lookups, resolved at startup and on reload
+static ICON_THEME: Mutex<Option<String>> = Mutex::new(None);
+
+// Parsed command line, for re-applying flags over the config on reload
+static CLI: OnceLock<Cli> = OnceLock::new();
+
+// Last cursor position and the window it was over. Written on every
+// CursorMoved, which would otherwise mean an update per pixel of movement.
+static LATEST_POINTER: Mutex<Option<(window::Id, iced::Point)>> = Mutex::new(None);
+
+// Command socket path (see ipc), resolved once at startup; None without
+// $XDG_RUNTIME_DIR
+static IPC_SOCKET: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+// Shown for items whose icon couldn't be resolved at all; monochrome, so it
+// takes the palette's symbolic color
+static FALLBACK_ICON: LazyLock<IconHandle> =
+    LazyLock::new(|| IconHandle::Symbolic(svg::Handle::from_memory(include_bytes!("../assets/fallback-icon.svg"))));
+
+// Design constants
+const MAX_PIXMAP_SIZE: i32 = 1024; // Larger pixmaps are treated as bogus and ignored
+const OVERLAY_SCALE: f32 = 0.5; // Badge edge length relative to the icon
+const ICON_BACKING_ALPHA: f32 = 0.35; // hover_background alpha scale for icon_backing = "always"
+const PASSIVE_OPACITY: f32 = 0.5; // Icon opacity of Passive items with dim_passive_items
+const SCROLL_PIXELS_PER_STEP: f32 = 20.0; // Touchpad pixel deltas per SNI scroll step
+const HOVER_IN_DELAY: Duration = Duration::from_millis(80); // Pointer passing over quicker than this doesn't highlight
+const TOOLTIP_DELAY: Duration = Duration::from_millis(500);
+const SCROLL_FEEDBACK_TIME: Duration = Duration::from_millis(800); // Scroll feedback stays up this long after the last notch
+const ICON_DEBOUNCE: Duration = Duration::from_millis(100); // Icon updates within this window collapse to the last
+const ANIMATED_ICON_GAP: Duration = Duration::from_millis(250); // Pixmap updates closer than this count as animation frames
+const ICON_FRAME_CACHE: usize = 16; // Converted frames kept per animated icon
+const POPUP_OPEN_GRACE: Duration = Duration::from_millis(150); // Presses elsewhere this soon after opening don't close it
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(300);
+const ABOUT_TO_SHOW_TIMEOUT: Duration = Duration::from_millis(250); // Open the cached menu if the app is slower
+const INITIAL_ITEMS_TIMEOUT: Duration = Duration::from_secs(2); // Upper bound on wait_for_initial_items
+const LIST_ITEMS_SETTLE: Duration = Duration::from_millis(300); // --list-items stops after this long without events
+const ACTIVATION_TOKEN_TIMEOUT: Duration = Duration::from_millis(200); // Activate goes ahead without a token after this
+const TRAY_BATCH_MAX: usize = 64; // A flood of tray events is still applied this many at a time
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10); // A tray connect taking longer counts as failed
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1); // Doubles per failed tray connect...
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30); // ...up to this
+const UNPINNED_SLOT: &str = "..."; // icon_order entry marking where unmatched icons go
+const FADE_IN_DURATION: Duration = Duration::from_millis(200); // New icons fade in over this long
+const POPUP_FADE_DURATION: Duration = Duration::from_millis(120); // Menus fade and slide in, and fade out, over this long
+const POPUP_SLIDE: f32 = 6.0; // Pixels a menu slides in from the bar's side
+const ANIMATION_FRAME: Duration = Duration::from_millis(16);
+
+#[derive(Debug, Clone, Default)]
 struct IconData {
     pixmap: Option<Vec<IconPixmap>>,
     icon_name: Option<String>,
     icon_theme_path: Option<String>,
 }
 
+#[derive(Debug, Clone)]
+struct TooltipText {
+    title: String,
+    body: String,
+}
+
+// Snapshot of an SNI item's properties at the time it is added
+#[derive(Debug, Clone)]
+struct ItemData {
+    id: String, // SNI Id, stable across restarts unlike the bus address
+    title: Option<String>,
+    status: Status,
+    category: Category,
+    icon: IconData,
+    attention_icon: IconData, // Shown instead of `icon` while status is NeedsAttention
+    overlay_icon: IconData,   // Badge drawn over the corner of the icon
+    tooltip: Option<TooltipText>,
+    menu_path: Option<String>, // DBus object path of the item's com.canonical.dbusmenu
+    menu: Option<TrayMenu>,
+}
+
 #[derive(Debug, Clone)]
 enum TrayEvent {
-    Add { address: String, icon: IconData },
+    Add { address: String, item: Box<ItemData> },
     Update { address: String, icon: IconData },
+    Menu { address: String, menu: TrayMenu },
+    Status { address: String, status: Status },
+    AttentionIcon { address: String, icon_name: Option<String> },
+    OverlayIcon { address: String, icon_name: Option<String> },
+    Title { address: String, title: Option<String> },
+    Tooltip { address: String, tooltip: Option<TooltipText> },
     Remove { address: String },
+    Connected,
+    InitialItemsSent, // Every item registered at connect time has been sent as Add
+    Disconnected,
     Tick, // Used for internal state machine transitions
 }
 
@@ -46,16 +167,71 @@ enum ClickType {
     Middle,
 }
 
+// SNI method the tray subscription calls on an item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SniCall {
+    Activate, // Falls back to ContextMenu for menu-only items or if Activate fails
+    ContextMenu,
+    SecondaryActivate,
+    Scroll(i32, Orientation), // Delta in whole steps
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Orientation {
+    Horizontal,
+    Vertical,
+}
+
+impl Orientation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Orientation::Horizontal => "horizontal",
+            Orientation::Vertical => "vertical",
+        }
+    }
+}
+
 // Manual Message enum - NOT using to_layer_message macro so we can control popup parenting
 #[derive(Debug, Clone)]
 enum Message {
     Tray(TrayEvent),
+    TrayBatch(Vec<TrayEvent>), // Events that arrived within tray_batch of each other, in order
     TrayIconClicked(String, ClickType), // address, click type
+    TrayIconDoubleClick(String),         // Second left press on the same icon within DOUBLE_CLICK_TIME
+    MenuPrepared(String, Option<TrayMenu>), // address, fresh layout if AboutToShow asked for one
     TrayIconHover(String, bool),         // address, is_hovered
-    MouseMoved(iced::Point),
+    HoverDelayElapsed(String),           // address hovered long enough to highlight
+    HoverGraceExpired(String),           // address whose exit grace window elapsed
+    IconDebounceElapsed(String),         // address whose pending icon update is due
+    IconResolved(String, IconSlot, IconData, Option<IconHandle>), // address, slot, source, result
+    TrayIconScroll(String, iced::mouse::ScrollDelta), // address, raw wheel/touchpad delta
+    ScrollFeedbackExpired,               // SCROLL_FEEDBACK_TIME passed since a scroll notch
+    BarScroll(iced::mouse::ScrollDelta), // Wheel over the bar outside any icon: moves icon focus
+    BarClicked(window::Id),              // Left press on the bar outside any icon (with on_bar_click_command)
+    ActivateFocusedIcon,                 // Keyboard Enter/Space with an icon focused
+    ClearIconFocus,                      // Keyboard Escape with an icon focused
+    ToggleTrayOverflow,                  // Chevron click: show or fold the collapsible icons
+    OverflowIndicatorClicked,            // "+K" click: carry out config.overflow_action
+    OverflowItemClicked(String),         // address picked in the overflow popup
+    TooltipDelayElapsed(String),         // address hovered long enough to show its tooltip
+    InitialItemsTimeout,                 // Stop waiting for initial items and show the tray
+    Tick,                                // Once-per-second clock refresh
+    ColorSchemeChanged(bool),            // Desktop now prefers dark (true) or light colors
+    ReloadConfig,                        // SIGHUP: re-read the config file
+    Quit,                                // SIGTERM/SIGINT: close the bars and exit
+    SurfaceChange(window::Id, LayershellCustomAction), // Reconfigure a bar's layer surface
+    AnimationTick,                       // Frame tick while an icon fades in or a popup fades
+    PointerPressed(window::Id),          // Uncaptured press in the given window
     ClosePopup,
+    MenuItemClicked(i32),                // dbusmenu item id
+    MenuHighlightMove(isize),            // Keyboard Up (-1) / Down (+1) in the open menu
+    MenuActivateHighlighted,             // Keyboard Enter in the open menu
     WindowResized(window::Id, iced::Size),
+    WindowClosed(window::Id),
+    WindowRescaled(window::Id, f32), // New scale factor for a window's output
+    Output(wayland::OutputEvent),    // An output appeared (also each one present at startup) or went away
     // Layershell actions with explicit parent control
+    OpenBar { id: window::Id, settings: NewLayerShellSettings },
     OpenPopup { parent: window::Id, popup: window::Id, settings: IcedNewPopupSettings },
     CloseWindow(window::Id),
 }
@@ -72,385 +248,2570 @@ impl TryInto<LayershellCustomActionWithId> for Message {
                     LayershellCustomAction::NewPopUp { settings, id: popup },
                 )
             ),
+            Message::OpenBar { id, settings } => Ok(
+                LayershellCustomActionWithId::new(
+                    None,
+                    LayershellCustomAction::NewLayerShell { settings, id },
+                )
+            ),
             Message::CloseWindow(id) => Ok(
                 LayershellCustomActionWithId::new(
                     Some(id),
                     LayershellCustomAction::RemoveWindow,
                 )
             ),
+            Message::SurfaceChange(id, action) => Ok(LayershellCustomActionWithId::new(Some(id), action)),
             other => Err(other),
         }
     }
 }
 
+#[derive(Debug, Clone)]
 enum IconHandle {
     Raster(image::Handle),
     Svg(svg::Handle),
+    Symbolic(svg::Handle), // Monochrome "-symbolic" SVG, recolored to palette.symbolic_color
+}
+
+// Which of an item's icons a resolution result is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IconSlot {
+    Main,
+    Attention,
+    Overlay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PopupKind {
+    Menu,
+    Tooltip,
+    Overflow, // Icons beyond config.max_visible_icons
+}
+
+// A popup fading out after ClosePopup; the window goes once the fade is done
+struct ClosingPopup {
+    started: Instant,
+    address: Option<String>, // popup_for_address at close time, so a menu keeps its entries
+    close_sent: bool,        // CloseWindow issued, waiting for WindowClosed
 }
 
 struct TrayItem {
+    id: String,
+    title: Option<String>,
     icon: Option<IconHandle>,
+    icon_data: IconData, // What `icon` was resolved from, to skip redundant updates
+    status: Status,
+    category: Category,
+    attention_icon: Option<IconHandle>,
+    attention_icon_data: IconData,
+    overlay_icon: Option<IconHandle>,
+    overlay_icon_data: IconData,
     hovered: bool,
+    hover_exit_at: Option<Instant>, // Pending exit, cleared if the pointer re-enters within the grace window
+    scroll_accum: (f32, f32),       // Fractional (x, y) scroll not yet sent as a whole step
+    hover_started: Option<Instant>, // When the pointer entered, for the highlight and tooltip delays
+    tooltip: Option<TooltipText>,
+    menu_path: Option<String>,
+    menu: Option<TrayMenu>,
+    opacity: f32,       // Eased fade-in progress, 1.0 once fully visible
+    added_at: Instant,  // When the item first appeared, for the fade-in
+    last_icon_update: Option<Instant>, // For spotting animated icons (see ANIMATED_ICON_GAP)
+    icon_frames: VecDeque<(u64, IconHandle)>, // Recent animation frames by pixmap hash, oldest first
 }
 
 struct State {
+    config: Config,
+    tray_connected: bool, // Whether the system tray client is currently connected
+    initial_items_ready: bool, // Startup items have arrived (or we gave up waiting)
     tray_items: HashMap<String, TrayItem>,
+    item_order: Vec<String>, // Addresses in the order they were first added
+    pending_icons: HashMap<String, IconData>, // Latest icon per address, applied when its debounce window ends
     mouse_position: (f32, f32),
-    main_bar_id: Option<window::Id>,   // The main bar window ID (for parenting popups)
-    bar_width: u32,                    // Actual bar width from Resized events
+    bars: HashMap<window::Id, iced::Size>, // One bar per output, with its size from Resized events
+    bar_outputs: HashMap<window::Id, String>, // Output each bar we opened is on
+    bar_configs: HashMap<window::Id, Config>, // config with each of those bars' [outputs."NAME"] section applied
+    last_left_press: Option<(String, Instant)>, // For telling double clicks from two single ones
+    pointer_bar: Option<window::Id>,   // Bar the pointer was last over (popups parent to it)
     active_popup: Option<window::Id>,  // Current popup window (only one at a time)
+    popup_opened_at: Option<Instant>,  // When active_popup opened, for POPUP_OPEN_GRACE
     popup_for_address: Option<String>, // Which tray item's popup is open
+    deferred_menu: Option<String>,     // Address right-clicked before any bar existed
+    popup_windows: HashMap<window::Id, (PopupKind, window::Id)>, // Popups we opened and their parent bars, until they report Closed
+    closing_popups: HashMap<window::Id, ClosingPopup>, // Popups fading out
+    focused_icon: Option<usize>,       // Index into focusable_addresses, moved by scrolling the bar
+    focus_scroll_accum: f32,           // Fractional bar scroll not yet turned into a focus step
+    tray_expanded: bool,               // Collapsible icons are shown inline (config.collapsible)
+    overflow_offset: usize,            // First visible icon when overflow_action cycles
+    menu_highlight: Option<usize>,     // Keyboard-highlighted row among the menu's actionable items
+    tooltip_popup: Option<(window::Id, String)>, // Tooltip window and the address it describes
+    scroll_feedback_at: Option<Instant>, // Last scroll notch, while the tooltip was opened as scroll feedback
+    scale_factor: f32,                 // Output scale of the bars, for icon resolution
+    system_dark: bool,                 // The desktop prefers dark colors (config.theme = "auto")
+    palette: Palette,                  // config colors over the dark or light base
+    clock_text: String,                // Current time rendered with config.clock_format
 }
 
-fn init() -> (State, iced::Task<Message>) {
+fn init(config: Config) -> (State, iced::Task<Message>) {
+    let wait_task = if config.wait_for_initial_items {
+        iced::Task::perform(tokio::time::sleep(INITIAL_ITEMS_TIMEOUT), |_| Message::InitialItemsTimeout)
+    } else {
+        iced::Task::none()
+    };
+
     (
         State {
+            initial_items_ready: !config.wait_for_initial_items,
+            tray_connected: false,
             tray_items: HashMap::new(),
+            item_order: Vec::new(),
+            pending_icons: HashMap::new(),
             mouse_position: (0.0, 0.0),
-            main_bar_id: None, // Will be set on first Resized event
-            bar_width: 1920,   // Default, will be updated on first Resized event
+            bars: HashMap::new(), // Filled in as each output's bar reports its size
+            bar_outputs: HashMap::new(),
+            bar_configs: HashMap::new(),
+            last_left_press: None,
+            pointer_bar: None,
             active_popup: None,
+            popup_opened_at: None,
             popup_for_address: None,
+            deferred_menu: None,
+            popup_windows: HashMap::new(),
+            closing_popups: HashMap::new(),
+            focused_icon: None,
+            focus_scroll_accum: 0.0,
+            tray_expanded: false,
+            overflow_offset: 0,
+            menu_highlight: None,
+            tooltip_popup: None,
+            scroll_feedback_at: None,
+            scale_factor: 1.0,
+            system_dark: true, // Until the portal reports the desktop's scheme
+            palette: config.palette(true),
+            clock_text: format_clock(&config.clock_format),
+            config,
         },
-        iced::Task::none(),
+        wait_task,
     )
 }
 
-fn namespace() -> String {
-    "vibebar".to_string()
-}
-
 fn update(state: &mut State, msg: Message) -> iced::Task<Message> {
+    sync_pointer(state);
     match msg {
         Message::Tray(event) => match event {
-            TrayEvent::Add { address, icon } | TrayEvent::Update { address, icon } => {
-                let icon_handle = resolve_icon(&icon);
-                let hovered = state.tray_items.get(&address).map(|i| i.hovered).unwrap_or(false);
-                state
+            TrayEvent::Add { address, item } => {
+                // Never inserted, so updates for the address find nothing to change
+                if !item_allowed(&state.config, &item.id) {
+                    tracing::debug!(%address, id = %item.id, "Hiding tray item per blacklist/whitelist");
+                    return iced::Task::none();
+                }
+                // A re-Add (the app re-registering, the watcher replaying) is an
+                // update: the app's properties are replaced while hover, scroll,
+                // fade and other UI state carry on
+                if !state.tray_items.contains_key(&address) {
+                    state.item_order.push(address.clone());
+                }
+                let initial_opacity = if state.config.reduce_motion { 1.0 } else { 0.0 };
+                let tray_item = state
                     .tray_items
-                    .insert(address, TrayItem { icon: icon_handle, hovered });
+                    .entry(address.clone())
+                    .or_insert_with(|| TrayItem::new(initial_opacity));
+                // Icons resolve off the UI thread; changed ones keep showing the old
+                // handle (a new item the placeholder) until they arrive
+                let resolve_tasks: Vec<_> = tray_item
+                    .set_properties(*item)
+                    .into_iter()
+                    .map(|slot| resolve_icon_task(address.clone(), slot, tray_item.icon_slot(slot).0.clone()))
+                    .collect();
+                return iced::Task::batch(resolve_tasks);
             }
-            TrayEvent::Remove { address } => {
-                state.tray_items.remove(&address);
+            TrayEvent::Update { address, icon } => {
+                // Hidden (blacklisted) items have nothing to update
+                let Some(item) = state.tray_items.get_mut(&address) else {
+                    return iced::Task::none();
+                };
+                // Animated icons (spinners and the like) send a new pixmap every
+                // frame; debouncing and the blocking pool would drop or delay
+                // frames, so convert them here and reuse frames seen recently
+                let now = Instant::now();
+                let animated = item.last_icon_update.is_some_and(|t| now.duration_since(t) < ANIMATED_ICON_GAP);
+                item.last_icon_update = Some(now);
+                if animated && show_animation_frame(item, &icon) {
+                    // A debounced update still pending is older than this frame
+                    state.pending_icons.remove(&address);
+                    return iced::Task::none();
+                }
+                // Coalesce bursts: the first update starts the window, later ones replace it
+                let first = state.pending_icons.insert(address.clone(), icon).is_none();
+                if first {
+                    return iced::Task::perform(tokio::time::sleep(ICON_DEBOUNCE), move |_| {
+                        Message::IconDebounceElapsed(address)
+                    });
+                }
             }
-            TrayEvent::Tick => {}
-        },
-        Message::TrayIconClicked(address, click_type) => {
-            match click_type {
-                ClickType::Right => {
-                    // Need main bar ID to parent the popup
-                    let Some(parent) = state.main_bar_id else {
-                        eprintln!("No main bar ID yet, can't open popup");
-                        return iced::Task::none();
+            TrayEvent::Menu { address, menu } => {
+                if let Some(item) = state.tray_items.get_mut(&address) {
+                    item.menu = Some(menu);
+                }
+            }
+            TrayEvent::Status { address, status } => {
+                if let Some(item) = state.tray_items.get_mut(&address) {
+                    item.status = status;
+                }
+            }
+            TrayEvent::Title { address, title } => {
+                if let Some(item) = state.tray_items.get_mut(&address) {
+                    item.title = title;
+                }
+            }
+            TrayEvent::Tooltip { address, tooltip } => {
+                if let Some(item) = state.tray_items.get_mut(&address) {
+                    item.tooltip = tooltip;
+                }
+            }
+            TrayEvent::AttentionIcon { address, icon_name } => {
+                if let Some(item) = state.tray_items.get_mut(&address) {
+                    let icon = IconData {
+                        pixmap: None,
+                        icon_name,
+                        icon_theme_path: item.icon_data.icon_theme_path.clone(),
                     };
-
-                    // Close any existing popup first
-                    let close_task = if let Some(existing_id) = state.active_popup.take() {
-                        state.popup_for_address = None;
-                        iced::Task::done(Message::CloseWindow(existing_id))
-                    } else {
-                        iced::Task::none()
+                    if !same_icon_data(&item.attention_icon_data, &icon) {
+                        item.attention_icon_data = icon.clone();
+                        return resolve_icon_task(address, IconSlot::Attention, icon);
+                    }
+                }
+            }
+            TrayEvent::OverlayIcon { address, icon_name } => {
+                if let Some(item) = state.tray_items.get_mut(&address) {
+                    let icon = IconData {
+                        pixmap: None,
+                        icon_name,
+                        icon_theme_path: item.icon_data.icon_theme_path.clone(),
                     };
+                    if !same_icon_data(&item.overlay_icon_data, &icon) {
+                        item.overlay_icon_data = icon.clone();
+                        return resolve_icon_task(address, IconSlot::Overlay, icon);
+                    }
+                }
+            }
+            TrayEvent::Remove { address } => {
+                state.tray_items.remove(&address);
+                state.pending_icons.remove(&address);
+                state.item_order.retain(|a| *a != address);
 
-                    // Open a popup menu below the icon
-                    let popup = window::Id::unique();
-                    state.active_popup = Some(popup);
-                    state.popup_for_address = Some(address);
-
-                    // Position: center below the clicked icon, clamped to bar width
-                    let menu_width = 200i32;
-                    let menu_height = 80i32;
-                    let bar_w = state.bar_width as i32;
-                    let (mouse_x, _mouse_y) = state.mouse_position;
-                    let margin = 4i32;
-
-                    // Prefer centered under click, clamp to bar edges
-                    let prefer_center = (mouse_x as i32) - (menu_width / 2);
-                    let min_x = margin;
-                    let max_x = bar_w - menu_width - margin;
-                    let x = prefer_center.clamp(min_x, max_x.max(min_x));
-
-                    let y = 30 + 6; // bar height + gap
-
-                    let open_task = iced::Task::done(Message::OpenPopup {
-                        parent,
-                        popup,
-                        settings: IcedNewPopupSettings {
-                            size: (menu_width as u32, menu_height as u32),
-                            position: (x, y),
+                // Don't leave popups around that reference an app which is gone
+                let menu_task = if state.popup_for_address.as_ref() == Some(&address) {
+                    iced::Task::done(Message::ClosePopup)
+                } else {
+                    iced::Task::none()
+                };
+                let tooltip_task = if state.tooltip_popup.as_ref().is_some_and(|(_, a)| *a == address) {
+                    close_tooltip(state)
+                } else {
+                    iced::Task::none()
+                };
+                return iced::Task::batch([menu_task, tooltip_task]);
+            }
+            TrayEvent::Connected => state.tray_connected = true,
+            TrayEvent::InitialItemsSent => state.initial_items_ready = true,
+            TrayEvent::Disconnected => {
+                // Failed reconnects also report Disconnected; only the loss itself notifies
+                let lost = std::mem::replace(&mut state.tray_connected, false);
+                if lost && state.config.notify_on_tray_loss {
+                    return iced::Task::perform(
+                        async {
+                            if let Err(e) = notify("vibebar: system tray disconnected", "").await {
+                                tracing::warn!(error = %e, "Failed to send tray loss notification");
+                            }
                         },
-                    });
-
-                    return iced::Task::batch([close_task, open_task]);
+                        |_| Message::Tray(TrayEvent::Tick),
+                    );
                 }
-                _ => {
-                    // Left and middle click - send to DBus
-                    if let Some(tx) = ACTIVATE_TX.get() {
-                        let (x, y) = state.mouse_position;
-                        let _ = tx.send((address, click_type, x as i32, y as i32));
-                    }
+            }
+            TrayEvent::Tick => {}
+        },
+        Message::TrayBatch(events) => {
+            let tasks: Vec<_> = events.into_iter().map(|event| update(state, Message::Tray(event))).collect();
+            return iced::Task::batch(tasks);
+        }
+        Message::TrayIconClicked(address, click_type) => {
+            // Dropped before anything reaches the app, overrides included
+            if click_type == ClickType::Middle && !state.config.enable_middle_click {
+                return iced::Task::none();
+            }
+            let Some(item) = state.tray_items.get(&address) else {
+                return iced::Task::none();
+            };
+            let action = click_action(&state.config, &item.id, click_type);
+
+            // Second presses count as double clicks only when there's something to send
+            if click_type == ClickType::Left && double_click_call(&state.config, &item.id).is_some() {
+                let now = Instant::now();
+                let is_double = state
+                    .last_left_press
+                    .take()
+                    .is_some_and(|(last, at)| last == address && now - at <= DOUBLE_CLICK_TIME);
+                if is_double {
+                    return iced::Task::done(Message::TrayIconDoubleClick(address));
                 }
+                state.last_left_press = Some((address.clone(), now));
+            }
+
+            return run_click_action(state, address, action);
+        }
+        Message::MenuPrepared(address, menu) => {
+            if let Some(menu) = menu
+                && let Some(item) = state.tray_items.get_mut(&address)
+            {
+                item.menu = Some(menu);
             }
+            // The item may have gone away while the app was preparing its menu
+            if state.tray_items.contains_key(&address) {
+                return open_menu_popup(state, address);
+            }
+        }
+        Message::TrayIconDoubleClick(address) => {
+            let Some(call) = state.tray_items.get(&address).and_then(|item| double_click_call(&state.config, &item.id))
+            else {
+                return iced::Task::none();
+            };
+            play_click_feedback(&state.config);
+            let (x, y) = state.mouse_position;
+            send_activation((address, call, x as i32, y as i32));
         }
         Message::ClosePopup => {
             if let Some(id) = state.active_popup.take() {
-                state.popup_for_address = None;
-                return iced::Task::done(Message::CloseWindow(id));
+                let address = state.popup_for_address.take();
+                state.menu_highlight = None;
+                if state.config.reduce_motion {
+                    return iced::Task::done(Message::CloseWindow(id));
+                }
+                // AnimationTick closes the window once it has faded out
+                state.closing_popups.insert(
+                    id,
+                    ClosingPopup {
+                        started: Instant::now(),
+                        address,
+                        close_sent: false,
+                    },
+                );
+            }
+        }
+        Message::MenuHighlightMove(step) => {
+            let count = popup_menu_ids(state).len();
+            if count == 0 {
+                state.menu_highlight = None;
+                return iced::Task::none();
+            }
+            // Wraps at both ends; with nothing highlighted, Down starts at the top and Up at the bottom
+            state.menu_highlight = Some(match state.menu_highlight {
+                Some(index) => (index.min(count - 1) as isize + step).rem_euclid(count as isize) as usize,
+                None if step < 0 => count - 1,
+                None => 0,
+            });
+        }
+        Message::MenuActivateHighlighted => {
+            let id = state
+                .menu_highlight
+                .and_then(|index| popup_menu_ids(state).get(index).copied());
+            if let Some(id) = id {
+                return iced::Task::done(Message::MenuItemClicked(id));
             }
         }
+        Message::MenuItemClicked(id) => {
+            let target = state
+                .popup_for_address
+                .as_ref()
+                .and_then(|address| {
+                    let path = state.tray_items.get(address)?.menu_path.clone()?;
+                    Some((address.clone(), path))
+                });
+
+            let close_task = iced::Task::done(Message::ClosePopup);
+            let Some((address, menu_path)) = target else {
+                tracing::warn!(item_id = id, "Menu item clicked but the item has no menu path");
+                return close_task;
+            };
+
+            return iced::Task::batch([menu_event_task(address, menu_path, id), close_task]);
+        }
         Message::WindowResized(id, size) => {
-            // Capture the main bar ID from the first window event (bar is first window)
-            if state.main_bar_id.is_none() && size.width > 100.0 {
-                state.main_bar_id = Some(id);
-                eprintln!("Captured main bar ID: {:?}, width: {}", id, size.width);
+            // Every window we didn't open as a popup is a bar on some output.
+            // Wait for a real size before placing popups against it.
+            if state.popup_windows.contains_key(&id) || size.width == 0.0 || size.height == 0.0 {
+                return iced::Task::none();
+            }
+            match state.bars.insert(id, size) {
+                None => {
+                    tracing::info!(window_id = ?id, width = size.width, "Captured bar window");
+                    if let Some(address) = state.deferred_menu.take()
+                        && state.tray_items.contains_key(&address)
+                    {
+                        return open_menu_popup(state, address);
+                    }
+                }
+                // The output's geometry changed (hotplug, mode switch). Popups are
+                // clamped to the bar when opened and can't be moved afterwards, so
+                // reopen the one on this bar against the new bounds. A tooltip is
+                // only shown while hovering; it just goes.
+                Some(old) if old != size => {
+                    tracing::info!(window_id = ?id, width = size.width, height = size.height, "Bar resized");
+                    let on_bar = |popup: &window::Id| state.popup_windows.get(popup).is_some_and(|(_, parent)| *parent == id);
+                    let tooltip_on_bar = state.tooltip_popup.as_ref().is_some_and(|(popup, _)| on_bar(popup));
+                    let popup_on_bar = state.active_popup.filter(on_bar).and_then(|popup| state.popup_windows.get(&popup));
+                    let popup_kind = popup_on_bar.map(|(kind, _)| *kind);
+                    let close_tooltip_task = if tooltip_on_bar { close_tooltip(state) } else { iced::Task::none() };
+                    let reopen_task = match (popup_kind, state.popup_for_address.clone()) {
+                        (Some(PopupKind::Menu), Some(address)) => open_menu_popup(state, address),
+                        (Some(PopupKind::Overflow), _) => open_overflow_popup(state),
+                        (Some(_), _) => iced::Task::done(Message::ClosePopup),
+                        (None, _) => iced::Task::none(),
+                    };
+                    return iced::Task::batch([close_tooltip_task, reopen_task]);
+                }
+                Some(_) => {}
+            }
+        }
+        Message::WindowRescaled(id, scale) => {
+            if !state.bars.contains_key(&id) || scale == state.scale_factor {
+                return iced::Task::none();
             }
-            // Only track bar width from the main window, not popups
-            if state.main_bar_id == Some(id) {
-                state.bar_width = size.width as u32;
+            // Named icons were looked up for the old scale; resolve them again.
+            // SVGs are rasterized by iced at physical size already, so only the
+            // chosen file changes.
+            state.scale_factor = scale;
+            set_icon_lookup_size(largest_icon_size(state), scale);
+            return resolve_all_icons(state);
+        }
+        Message::WindowClosed(id) => {
+            state.popup_windows.remove(&id);
+            state.closing_popups.remove(&id);
+            state.bar_outputs.remove(&id);
+            state.bar_configs.remove(&id);
+            // Outputs can go away, taking their bar (and any popup on it) along
+            if state.bars.remove(&id).is_some() && state.pointer_bar == Some(id) {
+                state.pointer_bar = None;
+            }
+            if state.active_popup == Some(id) {
+                state.active_popup = None;
+                state.popup_for_address = None;
+                state.menu_highlight = None;
+            }
+            if state.tooltip_popup.as_ref().is_some_and(|(popup, _)| *popup == id) {
+                state.tooltip_popup = None;
+                state.scroll_feedback_at = None;
             }
         }
+        Message::Output(wayland::OutputEvent::Added(output)) => return open_bar(state, output),
+        Message::Output(wayland::OutputEvent::Removed(name)) => {
+            // The compositor closes the bar's surface too; this just lets go of it
+            let gone: Vec<window::Id> =
+                state.bar_outputs.iter().filter(|(_, output)| **output == name).map(|(id, _)| *id).collect();
+            for id in &gone {
+                state.bar_outputs.remove(id);
+                state.bar_configs.remove(id);
+            }
+            tracing::info!(output = %name, "Output removed");
+            return iced::Task::batch(gone.into_iter().map(|id| iced::Task::done(Message::CloseWindow(id))));
+        }
         Message::TrayIconHover(address, is_hovered) => {
+            let grace = state.config.hover_grace;
+            let Some(item) = state.tray_items.get_mut(&address) else {
+                return iced::Task::none();
+            };
+
+            if is_hovered {
+                // Still lit from an exit inside the grace window; otherwise the
+                // highlight waits out HOVER_IN_DELAY so passing over doesn't flash it
+                item.hover_exit_at = None;
+                item.hover_started = Some(Instant::now());
+                let hover_task = if item.hovered {
+                    iced::Task::none()
+                } else {
+                    let address = address.clone();
+                    iced::Task::perform(tokio::time::sleep(HOVER_IN_DELAY), move |_| {
+                        Message::HoverDelayElapsed(address)
+                    })
+                };
+                let tooltip_task = iced::Task::perform(tokio::time::sleep(TOOLTIP_DELAY), move |_| {
+                    Message::TooltipDelayElapsed(address)
+                });
+                return iced::Task::batch([hover_task, tooltip_task]);
+            }
+
+            item.hover_started = None;
+            let grace_task = if grace.is_zero() {
+                item.hovered = false;
+                item.hover_exit_at = None;
+                iced::Task::none()
+            } else {
+                // Defer the exit so a quick exit/re-enter doesn't flicker the highlight
+                item.hover_exit_at = Some(Instant::now());
+                let address = address.clone();
+                iced::Task::perform(tokio::time::sleep(grace), move |_| {
+                    Message::HoverGraceExpired(address)
+                })
+            };
+
+            // The tooltip goes away immediately, regardless of the highlight grace
+            let close_task = if state.tooltip_popup.as_ref().is_some_and(|(_, a)| *a == address) {
+                close_tooltip(state)
+            } else {
+                iced::Task::none()
+            };
+            return iced::Task::batch([grace_task, close_task]);
+        }
+        Message::TooltipDelayElapsed(address) => {
+            let Some((parent, bar_size)) = active_bar(state) else {
+                return iced::Task::none();
+            };
+            // Never stack a tooltip on top of an open menu or another tooltip
+            if state.active_popup.is_some() || state.tooltip_popup.is_some() {
+                return iced::Task::none();
+            }
+            let Some(item) = state.tray_items.get(&address) else {
+                return iced::Task::none();
+            };
+            // Stale timer from an earlier hover that has since ended or restarted
+            if !item.hovered || item.hover_started.is_none_or(|t| t.elapsed() < TOOLTIP_DELAY) {
+                return iced::Task::none();
+            }
+            let Some(tooltip) = effective_tooltip(&state.config, item) else {
+                return iced::Task::none();
+            };
+            return open_tooltip(state, parent, bar_size, address, &tooltip);
+        }
+        Message::TrayIconScroll(address, delta) => {
+            let Some(item) = state.tray_items.get_mut(&address) else {
+                return iced::Task::none();
+            };
+
+            let (dx, dy) = match delta {
+                iced::mouse::ScrollDelta::Lines { x, y } => (x, y),
+                iced::mouse::ScrollDelta::Pixels { x, y } => {
+                    (x / SCROLL_PIXELS_PER_STEP, y / SCROLL_PIXELS_PER_STEP)
+                }
+            };
+            item.scroll_accum.0 += dx;
+            item.scroll_accum.1 += dy;
+
+            // Only whole steps go out; the remainder carries over to the next event
+            let (x, y) = state.mouse_position;
+            let axes = [
+                (Orientation::Horizontal, &mut item.scroll_accum.0),
+                (Orientation::Vertical, &mut item.scroll_accum.1),
+            ];
+            let mut scrolled = false;
+            for (orientation, accum) in axes {
+                let steps = accum.trunc();
+                if steps == 0.0 {
+                    continue;
+                }
+                *accum -= steps;
+                let call = SniCall::Scroll(steps as i32, orientation);
+                send_activation((address.clone(), call, x as i32, y as i32));
+                scrolled = true;
+            }
+            if scrolled {
+                return show_scroll_feedback(state, address);
+            }
+        }
+        Message::ScrollFeedbackExpired => {
+            // Each notch restarts the window; only the last timer closes the popup
+            if state.scroll_feedback_at.is_some_and(|t| t.elapsed() >= SCROLL_FEEDBACK_TIME) {
+                return close_tooltip(state);
+            }
+        }
+        Message::BarScroll(delta) => {
+            // Either axis moves focus; down/right is forward
+            let steps = match delta {
+                iced::mouse::ScrollDelta::Lines { x, y } => -(y + x),
+                iced::mouse::ScrollDelta::Pixels { x, y } => -(y + x) / SCROLL_PIXELS_PER_STEP,
+            };
+            state.focus_scroll_accum += steps;
+            let steps = state.focus_scroll_accum.trunc();
+            state.focus_scroll_accum -= steps;
+
+            let count = focusable_addresses(state).len();
+            if steps == 0.0 || count == 0 {
+                return iced::Task::none();
+            }
+            let steps = steps as isize;
+            let count = count as isize;
+            let next = match state.focused_icon {
+                Some(current) => (current.min(count as usize - 1) as isize + steps).rem_euclid(count),
+                // The first step lands on the first (or last) icon
+                None if steps > 0 => (steps - 1).rem_euclid(count),
+                None => (count + steps).rem_euclid(count),
+            };
+            state.focused_icon = Some(next as usize);
+        }
+        Message::ActivateFocusedIcon => {
+            let address = state
+                .focused_icon
+                .and_then(|index| focusable_addresses(state).get(index).map(|a| a.to_string()));
+            if let Some(address) = address
+                && let Some(item) = state.tray_items.get(&address)
+            {
+                let action = click_action(&state.config, &item.id, ClickType::Left);
+                return run_click_action(state, address, action);
+            }
+        }
+        Message::ClearIconFocus => {
+            state.focused_icon = None;
+        }
+        Message::ToggleTrayOverflow => {
+            state.tray_expanded = !state.tray_expanded;
+            // Indices shift as icons appear or disappear
+            state.focused_icon = None;
+        }
+        Message::OverflowIndicatorClicked => match &state.config.overflow_action {
+            OverflowAction::Popup => return open_overflow_popup(state),
+            OverflowAction::Cycle => {
+                state.overflow_offset += state.config.max_visible_icons.unwrap_or(1).max(1);
+                state.focused_icon = None;
+            }
+            OverflowAction::Command(command) => spawn_shell(command.clone()),
+        },
+        Message::BarClicked(id) => {
+            if let Some(command) = &state.config.on_bar_click_command {
+                spawn_shell(command.clone());
+            }
+            // The bar captures this press, so PointerPressed won't close the popup
+            return update(state, Message::PointerPressed(id));
+        }
+        Message::OverflowItemClicked(address) => {
+            let close_task = match state.active_popup.take() {
+                Some(id) => iced::Task::done(Message::CloseWindow(id)),
+                None => iced::Task::none(),
+            };
+            let Some(item) = state.tray_items.get(&address) else {
+                return close_task;
+            };
+            let action = click_action(&state.config, &item.id, ClickType::Left);
+            return iced::Task::batch([close_task, run_click_action(state, address, action)]);
+        }
+        Message::InitialItemsTimeout => {
+            state.initial_items_ready = true;
+        }
+        Message::IconDebounceElapsed(address) => {
+            if let Some(mut icon) = state.pending_icons.remove(&address)
+                && let Some(item) = state.tray_items.get_mut(&address)
+            {
+                // Icon updates don't carry the theme path; keep the one from Add
+                if icon.icon_theme_path.is_none() {
+                    icon.icon_theme_path = item.icon_data.icon_theme_path.clone();
+                }
+                if !same_icon_data(&item.icon_data, &icon) {
+                    item.icon_data = icon.clone();
+                    return resolve_icon_task(address, IconSlot::Main, icon);
+                }
+            }
+        }
+        Message::IconResolved(address, slot, icon, handle) => {
             if let Some(item) = state.tray_items.get_mut(&address) {
-                item.hovered = is_hovered;
+                let (data, target) = match slot {
+                    IconSlot::Main => (&item.icon_data, &mut item.icon),
+                    IconSlot::Attention => (&item.attention_icon_data, &mut item.attention_icon),
+                    IconSlot::Overlay => (&item.overlay_icon_data, &mut item.overlay_icon),
+                };
+                // A newer icon may have arrived while this one was resolving
+                if same_icon_data(data, &icon) {
+                    *target = handle;
+                }
             }
         }
-        Message::MouseMoved(point) => {
-            state.mouse_position = (point.x, point.y);
+        Message::HoverDelayElapsed(address) => {
+            // An exit clears hover_started; a later enter restarts the delay
+            if let Some(item) = state.tray_items.get_mut(&address)
+                && item.hover_started.is_some_and(|t| t.elapsed() >= HOVER_IN_DELAY)
+            {
+                item.hovered = true;
+            }
         }
-        // OpenPopup and CloseWindow are handled by TryInto -> layershell, not here
-        Message::OpenPopup { .. } | Message::CloseWindow(_) => {}
-    }
-    iced::Task::none()
-}
-
-fn resolve_icon(icon: &IconData) -> Option<IconHandle> {
-    // Prefer pixmap if available (pick largest for quality)
-    if let Some(ref pixmaps) = icon.pixmap {
-        if !pixmaps.is_empty() {
-            return pixmap_to_handle(pixmaps).map(IconHandle::Raster);
+        Message::HoverGraceExpired(address) => {
+            let grace = state.config.hover_grace;
+            if let Some(item) = state.tray_items.get_mut(&address) {
+                // A re-enter clears hover_exit_at; a later exit restarts the window
+                if item.hover_exit_at.is_some_and(|t| t.elapsed() >= grace) {
+                    item.hovered = false;
+                    item.hover_exit_at = None;
+                }
+            }
         }
-    }
-
-    // Fall back to icon_name lookup
-    if let Some(ref name) = icon.icon_name {
-        if !name.is_empty() {
-            return lookup_icon(name, icon.icon_theme_path.as_deref());
+        Message::Tick => {
+            state.clock_text = format_clock(&state.config.clock_format);
+        }
+        Message::ColorSchemeChanged(dark) => {
+            state.system_dark = dark;
+            state.palette = state.config.palette(dark);
+        }
+        Message::ReloadConfig => {
+            let Some(cli) = CLI.get() else {
+                return iced::Task::none();
+            };
+            tracing::info!("Reloading config");
+            return apply_config(state, load_config(cli));
+        }
+        Message::Quit => {
+            if let Some(Some(path)) = IPC_SOCKET.get() {
+                let _ = std::fs::remove_file(path);
+            }
+            return iced::exit();
+        }
+        Message::AnimationTick => {
+            for item in state.tray_items.values_mut() {
+                if item.opacity < 1.0 {
+                    let t = item.added_at.elapsed().as_secs_f32() / FADE_IN_DURATION.as_secs_f32();
+                    item.opacity = ease_out_cubic(t.min(1.0));
+                }
+            }
+            // Faded-out popups stay in closing_popups (drawn fully transparent)
+            // until WindowClosed, so they can't flash back in meanwhile
+            let close_tasks: Vec<_> = state
+                .closing_popups
+                .iter_mut()
+                .filter(|(_, popup)| !popup.close_sent && popup.started.elapsed() >= POPUP_FADE_DURATION)
+                .map(|(&id, popup)| {
+                    popup.close_sent = true;
+                    iced::Task::done(Message::CloseWindow(id))
+                })
+                .collect();
+            return iced::Task::batch(close_tasks);
         }
+        Message::PointerPressed(id) => {
+            // Presses inside the popup itself (padding, disabled rows) keep it open,
+            // as does the press that opened it if it is reported late
+            let just_opened = state.popup_opened_at.is_some_and(|t| t.elapsed() < POPUP_OPEN_GRACE);
+            if state.active_popup.is_some_and(|popup| popup != id) && !just_opened {
+                return iced::Task::done(Message::ClosePopup);
+            }
+        }
+        // OpenPopup, CloseWindow and SurfaceChange are handled by TryInto -> layershell, not here
+        Message::OpenPopup { .. } | Message::OpenBar { .. } | Message::CloseWindow(_) | Message::SurfaceChange(..) => {}
     }
-
-    None
+    iced::Task::none()
 }
 
-fn pixmap_to_handle(pixmaps: &[IconPixmap]) -> Option<image::Handle> {
-    // Pick the LARGEST pixmap for best quality (iced will downscale)
-    let pixmap = pixmaps.iter().max_by_key(|p| p.width * p.height)?;
-
-    // Convert ARGB to RGBA
-    let mut rgba = Vec::with_capacity(pixmap.pixels.len());
-    for chunk in pixmap.pixels.chunks(4) {
-        if chunk.len() == 4 {
-            let [a, r, g, b] = [chunk[0], chunk[1], chunk[2], chunk[3]];
-            rgba.extend_from_slice(&[r, g, b, a]);
-        }
+// config.click_command, for an audible or haptic cue on every dispatched click
+fn play_click_feedback(config: &Config) {
+    if let Some(command) = &config.click_command {
+        spawn_shell(command.clone());
     }
-
-    Some(image::Handle::from_rgba(
-        pixmap.width as u32,
-        pixmap.height as u32,
-        rgba,
-    ))
 }
 
-fn lookup_icon(name: &str, theme_path: Option<&str>) -> Option<IconHandle> {
-    // Try freedesktop icon lookup - request large size for quality
-    let path = freedesktop_icons::lookup(name)
-        .with_size(128) // Request large, iced will scale down
-        .with_cache()
-        .find();
-
-    let path = path.or_else(|| {
-        if let Some(tp) = theme_path {
-            let candidates = [
-                format!("{}/{}.svg", tp, name),
-                format!("{}/{}.png", tp, name),
-                format!("{}/hicolor/scalable/apps/{}.svg", tp, name),
-                format!("{}/hicolor/256x256/apps/{}.png", tp, name),
-                format!("{}/hicolor/128x128/apps/{}.png", tp, name),
-                format!("{}/hicolor/64x64/apps/{}.png", tp, name),
-            ];
-            for c in candidates {
-                let p = PathBuf::from(&c);
-                if p.exists() {
-                    return Some(p);
-                }
-            }
+// Runs a user command through the shell without blocking the UI; the child
+// is waited for on its own thread so it doesn't linger as a zombie
+fn spawn_shell(command: String) {
+    std::thread::spawn(move || {
+        if let Err(e) = std::process::Command::new("sh").arg("-c").arg(&command).status() {
+            tracing::warn!(%command, error = %e, "Failed to run command");
         }
-        None
     });
-
-    let path = path?;
-    load_icon_file(&path)
-}
-
-fn load_icon_file(path: &PathBuf) -> Option<IconHandle> {
-    let ext = path.extension()?.to_str()?;
-
-    match ext.to_lowercase().as_str() {
-        "svg" => Some(IconHandle::Svg(svg::Handle::from_path(path))),
-        "png" => load_png(path).map(IconHandle::Raster),
-        _ => None,
-    }
 }
 
-fn load_png(path: &PathBuf) -> Option<image::Handle> {
-    let data = std::fs::read(path).ok()?;
-    let img = image_crate::load_from_memory(&data).ok()?;
-    let rgba = img.to_rgba8();
-    let (w, h) = rgba.dimensions();
+// Opens the context menu popup for an item, replacing any open one
+fn open_menu_popup(state: &mut State, address: String) -> iced::Task<Message> {
+    // The popup opens on the bar that was clicked
+    let Some((parent, bar_size)) = active_bar(state) else {
+        // Clicked before any bar reported its size; open once one does
+        tracing::debug!(%address, "No bar window yet, deferring popup");
+        state.deferred_menu = Some(address);
+        return iced::Task::none();
+    };
+
+    // Close any existing popup (and the tooltip it would cover) first
+    let close_task = if let Some(existing_id) = state.active_popup.take() {
+        state.popup_for_address = None;
+        iced::Task::done(Message::CloseWindow(existing_id))
+    } else {
+        iced::Task::none()
+    };
+    let close_tooltip_task = close_tooltip(state);
+
+    // Open a popup menu next to the icon
+    let popup = window::Id::unique();
+    state.popup_windows.insert(popup, (PopupKind::Menu, parent));
+    let entries = state
+        .tray_items
+        .get(&address)
+        .and_then(|item| item.menu.as_ref())
+        .map_or(&[][..], |menu| &menu.submenus);
+    let (menu_width, menu_height) = menu_popup_size(&menu_header(state, &address), entries);
+    state.active_popup = Some(popup);
+    state.popup_opened_at = Some(Instant::now());
+    state.popup_for_address = Some(address);
+    state.menu_highlight = None;
+
+    // Position: centered on the clicked icon, clamped to the output
+    let (x, y, menu_width, menu_height) = popup_placement(state, (parent, bar_size), menu_width, menu_height);
+
+    let open_task = iced::Task::done(Message::OpenPopup {
+        parent,
+        popup,
+        settings: IcedNewPopupSettings {
+            size: (menu_width as u32, menu_height as u32),
+            position: (x, y),
+        },
+    });
 
-    Some(image::Handle::from_rgba(w, h, rgba.into_raw()))
+    iced::Task::batch([close_task, close_tooltip_task, open_task])
 }
 
-fn tray_icon_container_style(hovered: bool) -> container::Style {
-    if hovered {
-        container::Style {
-            background: Some(Background::Color(Color::from_rgba(1.0, 1.0, 1.0, 0.20))),
-            border: Border {
-                radius: 8.0.into(),
-                width: 1.0,
-                color: Color::from_rgba(1.0, 1.0, 1.0, 0.50),
-            },
-            ..Default::default()
-        }
-    } else {
-        container::Style::default()
+// Opens the popup listing the icons cut off by config.max_visible_icons,
+// replacing any open popup
+fn open_overflow_popup(state: &mut State) -> iced::Task<Message> {
+    let Some((parent, bar_size)) = active_bar(state) else {
+        return iced::Task::none();
+    };
+    let hidden = overflow_items(state);
+    if hidden.is_empty() {
+        return iced::Task::none();
     }
+    let longest = hidden.iter().map(|(_, item)| item_label(item).chars().count()).max().unwrap_or(0);
+    let width = (longest as f32 * MENU_CHAR_WIDTH + OVERFLOW_ICON_SIZE + 24.0 + MENU_PADDING * 2.0) as i32;
+    let height = (hidden.len() as f32 * MENU_ROW_HEIGHT + MENU_PADDING * 2.0) as i32;
+
+    let close_task = match state.active_popup.take() {
+        Some(existing_id) => iced::Task::done(Message::CloseWindow(existing_id)),
+        None => iced::Task::none(),
+    };
+    state.popup_for_address = None;
+    state.menu_highlight = None;
+    let close_tooltip_task = close_tooltip(state);
+
+    let popup = window::Id::unique();
+    state.popup_windows.insert(popup, (PopupKind::Overflow, parent));
+    state.active_popup = Some(popup);
+    state.popup_opened_at = Some(Instant::now());
+    let width = width.clamp(MENU_MIN_WIDTH, MENU_MAX_WIDTH);
+    let (x, y, width, height) = popup_placement(state, (parent, bar_size), width, height);
+
+    let open_task = iced::Task::done(Message::OpenPopup {
+        parent,
+        popup,
+        settings: IcedNewPopupSettings {
+            size: (width as u32, height as u32),
+            position: (x, y),
+        },
+    });
+    iced::Task::batch([close_task, close_tooltip_task, open_task])
 }
 
-fn view(state: &State, window_id: window::Id) -> Element<'_, Message> {
-    // Only render bar for the main bar window - anything else gets popup view
-    // This prevents flickering where unknown windows briefly show bar content
-    if state.main_bar_id != Some(window_id) {
-        return view_popup(state);
+// Swaps in a reloaded config. The tray connection and its items stay; bars
+// are reconfigured in place. Outputs and the namespace are fixed once the
+// bars exist, and items hidden by the old blacklist only show up again when
+// they re-register.
+fn apply_config(state: &mut State, mut config: Config) -> iced::Task<Message> {
+    if config.outputs != state.config.outputs || config.namespace != state.config.namespace {
+        tracing::warn!("Changes to outputs and namespace take effect after a restart");
     }
+    config.outputs = state.config.outputs.clone();
+    config.namespace = state.config.namespace.clone();
+
+    *ICON_THEME.lock().unwrap() = detect_icon_theme(config.icon_theme.as_deref());
+    LAUNCH_ON_ACTIVATE_FAILURE.store(config.launch_on_activate_failure, Ordering::Relaxed);
+    TRAY_BATCH_MS.store(config.tray_batch.as_millis() as u64, Ordering::Relaxed);
+    state.palette = config.palette(state.system_dark);
+    state.clock_text = format_clock(&config.clock_format);
 
-    // Main bar view
-    let tray_icons: Vec<Element<'_, Message>> = state
+    let hidden: Vec<String> = state
         .tray_items
         .iter()
-        .filter_map(|(address, item)| {
-            item.icon.as_ref().map(|handle| {
-                let icon_widget: Element<'_, Message> = match handle {
-                    IconHandle::Raster(h) => image(h.clone())
-                        .width(Length::Fixed(ICON_SIZE))
-                        .height(Length::Fixed(ICON_SIZE))
-                        .into(),
-                    IconHandle::Svg(h) => svg(h.clone())
-                        .width(Length::Fixed(ICON_SIZE))
-                        .height(Length::Fixed(ICON_SIZE))
-                        .into(),
-                };
+        .filter(|(_, item)| !item_allowed(&config, &item.id))
+        .map(|(address, _)| address.clone())
+        .collect();
+    for address in &hidden {
+        state.tray_items.remove(address);
+        state.pending_icons.remove(address);
+    }
+    state.item_order.retain(|address| !hidden.contains(address));
 
-                let hovered = item.hovered;
-                let addr = address.clone();
-                let addr2 = address.clone();
-                let addr3 = address.clone();
-                let addr4 = address.clone();
-                let addr5 = address.clone();
-
-                mouse_area(
-                    container(icon_widget)
-                        .width(Length::Fixed(CONTAINER_SIZE))
-                        .height(Length::Fixed(CONTAINER_SIZE))
-                        .center_x(Length::Fixed(CONTAINER_SIZE))
-                        .center_y(Length::Fixed(CONTAINER_SIZE))
-                        .style(move |_| tray_icon_container_style(hovered)),
-                )
-                .on_press(Message::TrayIconClicked(addr, ClickType::Left))
-                .on_right_press(Message::TrayIconClicked(addr2, ClickType::Right))
-                .on_middle_press(Message::TrayIconClicked(addr3, ClickType::Middle))
-                .on_enter(Message::TrayIconHover(addr4, true))
-                .on_exit(Message::TrayIconHover(addr5, false))
-                .into()
-            })
+    state.config = config;
+    state.bar_configs = state
+        .bar_outputs
+        .iter()
+        .map(|(&bar, output)| (bar, state.config.for_output(output)))
+        .collect();
+    set_icon_lookup_size(largest_icon_size(state), state.scale_factor);
+
+    let surface_tasks: Vec<_> = state
+        .bars
+        .keys()
+        .flat_map(|&bar| {
+            let config = bar_config(state, bar);
+            let (exclusive_zone, layer) = bar_layer(config);
+            [
+                LayershellCustomAction::AnchorSizeChange(bar_anchor(config.edge()), bar_size(config)),
+                LayershellCustomAction::ExclusiveZoneChange(exclusive_zone),
+                LayershellCustomAction::LayerChange(layer),
+            ]
+            .map(|action| iced::Task::done(Message::SurfaceChange(bar, action)))
         })
         .collect();
+    let surface_task = iced::Task::batch(surface_tasks);
 
-    let tray_row = row(tray_icons).spacing(4);
-
-    container(
-        row![
-            Space::new().width(Length::Fill),
-            Space::new().width(Length::Fixed(24.0)),
-            tray_row,
-            Space::new().width(Length::Fixed(10.0)),
-        ]
-        .align_y(iced::Alignment::Center),
-    )
-    .width(Length::Fill)
-    .height(Length::Fill)
-    .style(|_| container::Style {
-        background: Some(BAR_BG.into()),
-        ..Default::default()
-    })
-    .into()
+    // Popups were sized and placed for the old layout
+    state.focused_icon = None;
+    let close_task = iced::Task::done(Message::ClosePopup);
+    iced::Task::batch([surface_task, close_task, close_tooltip(state), resolve_all_icons(state)])
 }
 
-// Dark Prism menu colors
-const MENU_BG: Color = Color::from_rgb(24.0 / 255.0, 24.0 / 255.0, 27.0 / 255.0);
-const MENU_TEXT: Color = Color::from_rgb(244.0 / 255.0, 244.0 / 255.0, 245.0 / 255.0);
-const MENU_BORDER: Color = Color::from_rgba(255.0 / 255.0, 255.0 / 255.0, 255.0 / 255.0, 0.1);
-
-fn view_popup(state: &State) -> Element<'_, Message> {
-    use iced::widget::{button, column, text};
-
-    let label = state.popup_for_address
-        .as_ref()
-        .map(|a| format!("Menu for {}", a))
-        .unwrap_or_else(|| "Menu".to_string());
-
-    // Single container fills the window with rounded corners
-    // The transparent app background allows corners to show through
-    container(
-        column![
-            text(label).size(12).color(MENU_TEXT),
-            button(text("Close").size(12).color(MENU_TEXT))
-                .on_press(Message::ClosePopup)
-                .padding(4),
-        ]
-        .spacing(6)
-        .padding(8),
-    )
-    .width(Length::Fill)
-    .height(Length::Fill)
-    .style(|_| container::Style {
-        background: Some(Background::Color(MENU_BG)),
-        border: Border {
-            radius: 8.0.into(),
-            width: 1.0,
-            color: MENU_BORDER,
-        },
-        ..Default::default()
-    })
-    .into()
+// Applies the latest CursorMoved to mouse_position and pointer_bar. Positions
+// inside popups are in popup coordinates; only bar positions matter.
+fn sync_pointer(state: &mut State) {
+    let Some((id, point)) = LATEST_POINTER.lock().unwrap().take() else {
+        return;
+    };
+    if state.bars.contains_key(&id) {
+        state.pointer_bar = Some(id);
+        state.mouse_position = (point.x, point.y);
+    }
 }
 
-fn theme(_state: &State, _window_id: window::Id) -> Theme {
-    Theme::Dark
+fn ease_out_cubic(t: f32) -> f32 {
+    1.0 - (1.0 - t).powi(3)
 }
 
-// App style with transparent background (allows rounded corners on popups)
-fn style(_state: &State, theme: &Theme) -> iced::theme::Style {
-    iced::theme::Style {
-        background_color: Color::TRANSPARENT,
-        text_color: theme.palette().text,
+// How far a menu or overflow popup is through its transition: 0 is invisible,
+// 1 fully shown. Rises after opening and falls back while closing.
+fn popup_progress(state: &State, id: window::Id) -> f32 {
+    if state.config.reduce_motion {
+        return 1.0;
+    }
+    let eased = |since: Instant| ease_out_cubic((since.elapsed().as_secs_f32() / POPUP_FADE_DURATION.as_secs_f32()).min(1.0));
+    if let Some(closing) = state.closing_popups.get(&id) {
+        1.0 - eased(closing.started)
+    } else if state.active_popup == Some(id)
+        && let Some(opened_at) = state.popup_opened_at
+    {
+        eased(opened_at)
+    } else {
+        1.0
     }
 }
 
+// Whether a popup transition needs frame ticks
+fn popup_animating(state: &State) -> bool {
+    let opening = state
+        .popup_opened_at
+        .is_some_and(|t| state.active_popup.is_some() && t.elapsed() < POPUP_FADE_DURATION);
+    !state.config.reduce_motion && (opening || state.closing_popups.values().any(|popup| !popup.close_sent))
+}
+
+// Sends a dbusmenu "clicked" event for the item in the background
+fn menu_event_task(address: String, menu_path: String, id: i32) -> iced::Task<Message> {
+    iced::Task::perform(
+        async move {
+            if let Err(e) = dbusmenu_event(&address, &menu_path, id).await {
+                tracing::error!(%address, item_id = id, error = %e, "dbusmenu Event failed");
+                return None;
+            }
+            // Clicks may flip checkmarks and radio items, and not every app
+            // asks for a new layout on the next AboutToShow; fetch it now so
+            // the menu shows the new state when it next opens
+            let menu = dbusmenu_get_layout(&address, &menu_path)
+                .await
+                .inspect_err(|e| tracing::debug!(%address, error = %e, "dbusmenu GetLayout failed"))
+                .ok()?;
+            Some((address, menu))
+        },
+        |result| match result {
+            Some((address, menu)) => Message::Tray(TrayEvent::Menu { address, menu }),
+            None => Message::Tray(TrayEvent::Tick),
+        },
+    )
+}
+
+// Sends the root AboutToShow so apps can populate dynamic entries, and
+// fetches the new layout when they report it changed. None keeps the
+// cached menu, including when the app doesn't answer in time.
+async fn prepare_menu(address: &str, menu_path: &str) -> Option<TrayMenu> {
+    let needs_update = match tokio::time::timeout(ABOUT_TO_SHOW_TIMEOUT, dbusmenu_about_to_show(address, menu_path, 0)).await {
+        Ok(Ok(needs_update)) => needs_update,
+        Ok(Err(e)) => {
+            tracing::debug!(%address, error = %e, "dbusmenu AboutToShow failed");
+            return None;
+        }
+        Err(_) => {
+            tracing::debug!(%address, "dbusmenu AboutToShow timed out");
+            return None;
+        }
+    };
+    if !needs_update {
+        return None;
+    }
+    dbusmenu_get_layout(address, menu_path)
+        .await
+        .inspect_err(|e| tracing::warn!(%address, error = %e, "dbusmenu GetLayout failed"))
+        .ok()
+}
+
+// First clickable menu entry whose label mentions quit, exit or close (case-insensitive)
+fn find_quit_item(items: &[MenuItem]) -> Option<i32> {
+    items
+        .iter()
+        .filter(|i| i.visible && i.enabled && !matches!(i.menu_type, MenuType::Separator))
+        .find_map(|item| {
+            if !item.submenu.is_empty() {
+                return find_quit_item(&item.submenu);
+            }
+            let label = menu_label(item).to_lowercase();
+            ["quit", "exit", "close"]
+                .iter()
+                .any(|word| label.contains(word))
+                .then_some(item.id)
+        })
+}
+
+// The config a bar is drawn with: its output's, else (for bars layershell
+// opened on its own) the top-level one
+fn bar_config(state: &State, bar: window::Id) -> &Config {
+    state.bar_configs.get(&bar).unwrap_or(&state.config)
+}
+
+// Icons are looked up once for every bar, at the largest size any shows them
+fn largest_icon_size(state: &State) -> f32 {
+    state.bar_configs.values().map(|config| config.icon_size).fold(state.config.icon_size, f32::max)
+}
+
+// Bar to parent popups to: the one under the pointer, else any bar
+fn active_bar(state: &State) -> Option<(window::Id, iced::Size)> {
+    state
+        .pointer_bar
+        .and_then(|id| state.bars.get(&id).map(|size| (id, *size)))
+        .or_else(|| state.bars.iter().next().map(|(id, size)| (*id, *size)))
+}
+
+// Popup origin just off the bar's free edge (below a top bar, above a bottom
+// bar, beside a side bar), centered on the pointer and kept on the output.
+// Returns (x, y, width, height); the size along the bar shrinks when the
+// popup wouldn't otherwise fit.
+fn popup_placement(
+    state: &State,
+    (bar_id, bar): (window::Id, iced::Size),
+    popup_width: i32,
+    popup_height: i32,
+) -> (i32, i32, i32, i32) {
+    let (mouse_x, mouse_y) = state.mouse_position;
+    let thickness = bar_config(state, bar_id).bar_height as i32;
+    let gap = 6;
+
+    match state.config.edge() {
+        BarPosition::Top | BarPosition::Bottom => {
+            let (x, width) = popup_offset(mouse_x as i32, bar.width as i32, popup_width);
+            let y = if state.config.edge() == BarPosition::Top {
+                thickness + gap
+            } else {
+                -(popup_height + gap)
+            };
+            (x, y, width, popup_height)
+        }
+        BarPosition::Left | BarPosition::Right => {
+            let (y, height) = popup_offset(mouse_y as i32, bar.height as i32, popup_height);
+            let x = if state.config.edge() == BarPosition::Left {
+                thickness + gap
+            } else {
+                -(popup_width + gap)
+            };
+            (x, y, popup_width, height)
+        }
+    }
+}
+
+// Placement along the bar's length. The bar is anchored to both ends of its
+// edge, so that length is the full output width (or height, when vertical).
+fn popup_offset(pointer: i32, output_extent: i32, popup_extent: i32) -> (i32, i32) {
+    let margin = 4i32;
+    let extent = popup_extent.min(output_extent - margin * 2).max(1);
+
+    // Prefer centered on the click, clamp to the output edges
+    let prefer_center = pointer - extent / 2;
+    let min = margin;
+    let max = output_extent - extent - margin;
+    (prefer_center.clamp(min, max.max(min)), extent)
+}
+
+// An invalid strftime string would otherwise panic inside chrono's Display impl
+fn format_clock(format: &str) -> String {
+    use std::fmt::Write;
+
+    let now = chrono::Local::now();
+    let mut text = String::new();
+    if write!(text, "{}", now.format(format)).is_err() {
+        return now.format("%H:%M").to_string();
+    }
+    text
+}
+
+fn open_tooltip(
+    state: &mut State,
+    parent: window::Id,
+    bar_size: iced::Size,
+    address: String,
+    tooltip: &TooltipText,
+) -> iced::Task<Message> {
+    let (width, height) = tooltip_size(tooltip);
+    let (x, y, width, height) = popup_placement(state, (parent, bar_size), width as i32, height as i32);
+    let popup = window::Id::unique();
+    state.popup_windows.insert(popup, (PopupKind::Tooltip, parent));
+    state.tooltip_popup = Some((popup, address));
+
+    iced::Task::done(Message::OpenPopup {
+        parent,
+        popup,
+        settings: IcedNewPopupSettings {
+            size: (width as u32, height as u32),
+            position: (x, y),
+        },
+    })
+}
+
+// After a scroll notch, shows the item's tooltip (or title) so the app's
+// updated state, e.g. a new volume, is visible. A hover tooltip that is
+// already up is left alone rather than turned into a transient one.
+fn show_scroll_feedback(state: &mut State, address: String) -> iced::Task<Message> {
+    let showing = state.tooltip_popup.as_ref().is_some_and(|(_, a)| *a == address);
+    if showing && state.scroll_feedback_at.is_none() {
+        return iced::Task::none();
+    }
+    let timer = iced::Task::perform(tokio::time::sleep(SCROLL_FEEDBACK_TIME), |_| Message::ScrollFeedbackExpired);
+    if showing {
+        state.scroll_feedback_at = Some(Instant::now());
+        return timer;
+    }
+
+    if state.active_popup.is_some() {
+        return iced::Task::none();
+    }
+    let Some((parent, bar_size)) = active_bar(state) else {
+        return iced::Task::none();
+    };
+    let Some(tooltip) = state.tray_items.get(&address).and_then(|item| feedback_text(&state.config, item)) else {
+        return iced::Task::none();
+    };
+    let close_task = close_tooltip(state);
+    let open_task = open_tooltip(state, parent, bar_size, address, &tooltip);
+    state.scroll_feedback_at = Some(Instant::now());
+    iced::Task::batch([close_task, open_task, timer])
+}
+
+// The tooltip, or just the title for items without one
+fn feedback_text(config: &Config, item: &TrayItem) -> Option<TooltipText> {
+    effective_tooltip(config, item).or_else(|| {
+        let title = item.title.clone().filter(|t| !t.is_empty())?;
+        Some(TooltipText { title, body: String::new() })
+    })
+}
+
+fn close_tooltip(state: &mut State) -> iced::Task<Message> {
+    state.scroll_feedback_at = None;
+    match state.tooltip_popup.take() {
+        Some((id, _)) => iced::Task::done(Message::CloseWindow(id)),
+        None => iced::Task::none(),
+    }
+}
+
+fn item_data(item: &StatusNotifierItem, menu: Option<TrayMenu>) -> Box<ItemData> {
+    Box::new(ItemData {
+        id: item.id.clone(),
+        title: item.title.clone(),
+        status: item.status,
+        category: item.category,
+        icon: IconData {
+            pixmap: item.icon_pixmap.clone(),
+            icon_name: item.icon_name.clone(),
+            icon_theme_path: item.icon_theme_path.clone(),
+        },
+        attention_icon: IconData {
+            pixmap: item.attention_icon_pixmap.clone(),
+            icon_name: item.attention_icon_name.clone(),
+            icon_theme_path: item.icon_theme_path.clone(),
+        },
+        overlay_icon: IconData {
+            pixmap: item.overlay_icon_pixmap.clone(),
+            icon_name: item.overlay_icon_name.clone(),
+            icon_theme_path: item.icon_theme_path.clone(),
+        },
+        tooltip: item.tool_tip.as_ref().and_then(tooltip_text),
+        menu_path: item.menu.clone(),
+        menu,
+    })
+}
+
+// The client's item map. A panic inside system_tray while holding the lock
+// poisons it, but the map itself is still usable, so keep reading it rather
+// than taking the whole tray down with it.
+fn lock_items(items: &Mutex<BaseMap>) -> MutexGuard<'_, BaseMap> {
+    items.lock().unwrap_or_else(|e| {
+        tracing::warn!("Tray item map lock was poisoned; reading it anyway");
+        e.into_inner()
+    })
+}
+
+// Snapshot of every item the client currently knows about
+fn registered_items(client: &Client) -> Vec<(String, Box<ItemData>)> {
+    let items = client.items();
+    let guard = lock_items(&items);
+    guard
+        .iter()
+        .map(|(address, (item, menu))| (address.clone(), item_data(item, menu.clone())))
+        .collect()
+}
+
+fn tooltip_text(tooltip: &Tooltip) -> Option<TooltipText> {
+    tooltip_text_from(&tooltip.title, &tooltip.description)
+}
+
+// None when there is nothing to show
+fn tooltip_text_from(title: &str, body: &str) -> Option<TooltipText> {
+    let title = title.trim();
+    let body = body.trim();
+    if title.is_empty() && body.is_empty() {
+        return None;
+    }
+    Some(TooltipText {
+        title: title.to_string(),
+        body: body.to_string(),
+    })
+}
+
+// The tooltip to display for an item: a configured override if one matches,
+// otherwise whatever the app provided
+fn effective_tooltip(config: &Config, item: &TrayItem) -> Option<TooltipText> {
+    let title = item.title.as_deref().filter(|t| !t.is_empty());
+    let rule = config.tooltip_overrides.iter().find(|o| {
+        glob_match(&o.pattern, &item.id) || title.is_some_and(|t| glob_match(&o.pattern, t))
+    });
+    let Some(rule) = rule else {
+        return item.tooltip.clone();
+    };
+
+    let text = rule
+        .text
+        .replace("{title}", title.unwrap_or(&item.id))
+        .replace("{id}", &item.id);
+    let (tooltip_title, body) = match &item.tooltip {
+        Some(own) if rule.append && !own.body.is_empty() => {
+            (own.title.clone(), format!("{}\n{text}", own.body))
+        }
+        Some(own) => (own.title.clone(), text),
+        None => (title.unwrap_or_default().to_string(), text),
+    };
+    tooltip_text_from(&tooltip_title, &body)
+}
+
+// config.blacklist and config.whitelist, matched on the SNI id (bus
+// addresses change every time the app restarts)
+fn item_allowed(config: &Config, id: &str) -> bool {
+    let whitelisted = config.whitelist.is_empty() || config.whitelist.iter().any(|pattern| glob_match(pattern, id));
+    whitelisted && !config.blacklist.iter().any(|pattern| glob_match(pattern, id))
+}
+
+// Shell-style glob supporting '*' (any run) and '?' (any one char)
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None; // (pattern idx after '*', text idx)
+
+    while ti < t.len() {
+        match p.get(pi) {
+            Some('*') => {
+                backtrack = Some((pi + 1, ti));
+                pi += 1;
+            }
+            Some(&c) if c == '?' || c == t[ti] => {
+                pi += 1;
+                ti += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    pi = bp;
+                    ti = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+// Drops cached lookups and resolves every item's icons again, after the
+// lookup size or theme changed
+fn resolve_all_icons(state: &mut State) -> iced::Task<Message> {
+    ICON_CACHE.lock().unwrap().clear();
+    // Cached animation frames were converted for the old icon size
+    for item in state.tray_items.values_mut() {
+        item.icon_frames.clear();
+    }
+    let tasks = state.tray_items.iter().flat_map(|(address, item)| {
+        [IconSlot::Main, IconSlot::Attention, IconSlot::Overlay]
+            .map(|slot| resolve_icon_task(address.clone(), slot, item.icon_slot(slot).0.clone()))
+    });
+    iced::Task::batch(tasks)
+}
+
+// Resolves on the blocking pool, since name lookups hit the disk; the result
+// comes back as IconResolved
+fn resolve_icon_task(address: String, slot: IconSlot, icon: IconData) -> iced::Task<Message> {
+    iced::Task::perform(
+        async move {
+            tokio::task::spawn_blocking(move || {
+                let handle = resolve_icon(&icon);
+                (icon, handle)
+            })
+            .await
+            .ok()
+        },
+        move |resolved| match resolved {
+            Some((icon, handle)) => Message::IconResolved(address, slot, icon, handle),
+            None => Message::Tray(TrayEvent::Tick),
+        },
+    )
+}
+
+fn resolve_icon(icon: &IconData) -> Option<IconHandle> {
+    // Prefer pixmap if available (pick largest for quality)
+    if let Some(ref pixmaps) = icon.pixmap
+        && let Some(handle) = pixmap_to_handle(pixmaps)
+    {
+        return Some(IconHandle::Raster(handle));
+    }
+
+    // Fall back to icon_name lookup
+    if let Some(ref name) = icon.icon_name
+        && !name.is_empty()
+    {
+        return lookup_icon(name, icon.icon_theme_path.as_deref());
+    }
+
+    None
+}
+
+fn same_icon_data(a: &IconData, b: &IconData) -> bool {
+    let same_pixmaps = match (&a.pixmap, &b.pixmap) {
+        (Some(a), Some(b)) => {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(a, b)| {
+                    a.width == b.width && a.height == b.height && a.pixels == b.pixels
+                })
+        }
+        (None, None) => true,
+        _ => false,
+    };
+    same_pixmaps && a.icon_name == b.icon_name && a.icon_theme_path == b.icon_theme_path
+}
+
+// Shows a pixmap icon update straight away, converting it unless it's one of
+// the item's cached frames. Pixmaps win over names in resolve_icon, so the
+// result is the same; false if there's no usable pixmap to show.
+fn show_animation_frame(item: &mut TrayItem, icon: &IconData) -> bool {
+    let Some(pixmaps) = icon.pixmap.as_deref() else {
+        return false;
+    };
+    let mut hasher = DefaultHasher::new();
+    for pixmap in pixmaps {
+        (pixmap.width, pixmap.height, &pixmap.pixels).hash(&mut hasher);
+    }
+    let hash = hasher.finish();
+
+    let handle = match item.icon_frames.iter().find(|(h, _)| *h == hash) {
+        // Reusing the handle also lets the renderer keep its uploaded texture
+        Some((_, handle)) => handle.clone(),
+        None => {
+            let Some(handle) = pixmap_to_handle(pixmaps).map(IconHandle::Raster) else {
+                return false;
+            };
+            if item.icon_frames.len() == ICON_FRAME_CACHE {
+                item.icon_frames.pop_front();
+            }
+            item.icon_frames.push_back((hash, handle.clone()));
+            handle
+        }
+    };
+    // Icon updates don't carry the theme path; keep the one from Add
+    let icon_theme_path = icon.icon_theme_path.clone().or_else(|| item.icon_data.icon_theme_path.take());
+    item.icon = Some(handle);
+    item.icon_data = IconData { icon_theme_path, ..icon.clone() };
+    true
+}
+
+fn pixmap_to_handle(pixmaps: &[IconPixmap]) -> Option<image::Handle> {
+    // The smallest pixmap that covers the physical icon size downscales the
+    // least; if none is big enough, the largest upscales the least
+    let target = i32::from(ICON_LOOKUP_SIZE.load(Ordering::Relaxed));
+    // Some apps send empty pixmaps while starting up; skip those and absurd sizes
+    let usable = || {
+        pixmaps
+            .iter()
+            .filter(|p| p.width > 0 && p.height > 0 && p.width.max(p.height) <= MAX_PIXMAP_SIZE)
+    };
+    let pixmap = usable()
+        .filter(|p| p.width.min(p.height) >= target)
+        .min_by_key(|p| p.width * p.height)
+        .or_else(|| usable().max_by_key(|p| p.width * p.height))?;
+
+    // Truncated or padded buffers would render as garbage; let the caller
+    // fall back to the icon name instead
+    let expected_len = usize::try_from(pixmap.width)
+        .ok()?
+        .checked_mul(usize::try_from(pixmap.height).ok()?)?
+        .checked_mul(4)?;
+    if pixmap.pixels.len() != expected_len {
+        return None;
+    }
+
+    // Convert ARGB to RGBA
+    let mut rgba = Vec::with_capacity(pixmap.pixels.len());
+    for chunk in pixmap.pixels.chunks_exact(4) {
+        let [a, r, g, b] = [chunk[0], chunk[1], chunk[2], chunk[3]];
+        rgba.extend_from_slice(&[r, g, b, a]);
+    }
+
+    Some(image::Handle::from_rgba(
+        pixmap.width as u32,
+        pixmap.height as u32,
+        rgba,
+    ))
+}
+
+// Icon theme from config.icon_theme, else $GTK_ICON_THEME, else GNOME's
+// interface setting. None keeps freedesktop_icons' own default.
+fn detect_icon_theme(configured: Option<&str>) -> Option<String> {
+    if let Some(theme) = configured.filter(|t| !t.is_empty()) {
+        return Some(theme.to_string());
+    }
+    if let Ok(theme) = std::env::var("GTK_ICON_THEME")
+        && !theme.is_empty()
+    {
+        return Some(theme);
+    }
+
+    let output = std::process::Command::new("gsettings")
+        .args(["get", "org.gnome.desktop.interface", "icon-theme"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // gsettings prints a GVariant string, e.g. 'Adwaita'
+    let theme = String::from_utf8_lossy(&output.stdout).trim().trim_matches('\'').to_string();
+    (!theme.is_empty()).then_some(theme)
+}
+
+fn set_icon_lookup_size(icon_size: f32, scale: f32) {
+    let size = (icon_size * scale).ceil().clamp(1.0, u16::MAX as f32) as u16;
+    ICON_LOOKUP_SIZE.store(size, Ordering::Relaxed);
+}
+
+fn lookup_icon(name: &str, theme_path: Option<&str>) -> Option<IconHandle> {
+    let key = (name.to_string(), theme_path.map(str::to_string));
+    if let Some(cached) = ICON_CACHE.lock().unwrap().get(&key) {
+        return cached.clone();
+    }
+
+    let handle = lookup_icon_uncached(name, theme_path);
+    ICON_CACHE.lock().unwrap().insert(key, handle.clone());
+    handle
+}
+
+fn lookup_icon_uncached(name: &str, theme_path: Option<&str>) -> Option<IconHandle> {
+    // Try freedesktop icon lookup - request large size for quality
+    let mut lookup = freedesktop_icons::lookup(name)
+        .with_size(ICON_LOOKUP_SIZE.load(Ordering::Relaxed))
+        .with_cache();
+    let theme = ICON_THEME.lock().unwrap().clone();
+    if let Some(theme) = theme.as_deref() {
+        lookup = lookup.with_theme(theme);
+    }
+    let path = lookup.find();
+
+    let path = path.or_else(|| find_in_theme_path(std::path::Path::new(theme_path?), name));
+
+    let path = path?;
+    load_icon_file(&path)
+}
+
+// Apps with a custom IconThemePath (often their own install dir) lay it out
+// every which way: flat, hicolor-style, or sizes without the theme prefix.
+// Searches a few levels deep for "<name>.svg" or a png/xpm/ico raster,
+// preferring scalable art, then the smallest raster at least ICON_LOOKUP_SIZE.
+fn find_in_theme_path(root: &std::path::Path, name: &str) -> Option<PathBuf> {
+    const MAX_DEPTH: usize = 4;
+
+    let target = u32::from(ICON_LOOKUP_SIZE.load(Ordering::Relaxed));
+    let mut best: Option<(PathBuf, Option<u32>)> = None; // None size = scalable
+    let mut dirs = vec![(root.to_path_buf(), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                if depth < MAX_DEPTH {
+                    dirs.push((path, depth + 1));
+                }
+                continue;
+            }
+            if path.file_stem().and_then(|s| s.to_str()) != Some(name) {
+                continue;
+            }
+            let size = match path.extension().and_then(|e| e.to_str()) {
+                Some("svg") => None,
+                Some("png" | "xpm" | "ico") => Some(theme_dir_size(path.strip_prefix(root).unwrap_or(&path)).unwrap_or(0)),
+                _ => continue,
+            };
+            let better = match (&best, size) {
+                (None, _) => true,
+                (Some((_, None)), _) => false,
+                (Some((_, Some(_))), None) => true,
+                (Some((_, Some(current))), Some(size)) => {
+                    let (current, size) = (*current, size);
+                    if current >= target { size >= target && size < current } else { size > current }
+                }
+            };
+            if better {
+                best = Some((path, size));
+            }
+        }
+    }
+    best.map(|(path, _)| path)
+}
+
+// Pixel size from a theme-style directory such as "48x48/apps" or "32"
+fn theme_dir_size(relative: &std::path::Path) -> Option<u32> {
+    relative.parent()?.components().find_map(|component| {
+        let component = component.as_os_str().to_str()?;
+        let (width, _) = component.split_once('x').unwrap_or((component, component));
+        width.parse().ok()
+    })
+}
+
+fn load_icon_file(path: &PathBuf) -> Option<IconHandle> {
+    let ext = path.extension()?.to_str()?;
+
+    match ext.to_lowercase().as_str() {
+        "svg" if is_symbolic(path) => Some(IconHandle::Symbolic(svg::Handle::from_path(path))),
+        "svg" => Some(IconHandle::Svg(svg::Handle::from_path(path))),
+        "png" | "ico" => load_raster(path).map(IconHandle::Raster),
+        "xpm" => load_xpm(path).map(IconHandle::Raster),
+        _ => None,
+    }
+}
+
+// Symbolic icons follow the freedesktop "<name>-symbolic" naming convention
+fn is_symbolic(path: &std::path::Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem.ends_with("-symbolic"))
+}
+
+// Any format the image crate detects; for .ico that is the largest entry
+fn load_raster(path: &PathBuf) -> Option<image::Handle> {
+    let data = std::fs::read(path).ok()?;
+    let img = image_crate::load_from_memory(&data).ok()?;
+    let rgba = img.to_rgba8();
+    let (w, h) = rgba.dimensions();
+
+    Some(image::Handle::from_rgba(w, h, rgba.into_raw()))
+}
+
+fn load_xpm(path: &PathBuf) -> Option<image::Handle> {
+    let source = std::fs::read_to_string(path).ok()?;
+    let (w, h, rgba) = xpm::decode(&source)?;
+    Some(image::Handle::from_rgba(w, h, rgba))
+}
+
+fn tray_icon_container_style(
+    palette: &Palette,
+    backing: IconBacking,
+    hovered: bool,
+    needs_attention: bool,
+) -> container::Style {
+    let background = match backing {
+        IconBacking::None => None,
+        IconBacking::Hover | IconBacking::Always if hovered || needs_attention => Some(palette.hover_background),
+        // A faint version of the hover backing, so dark icons stand out from a dark bar
+        IconBacking::Always => Some(Color {
+            a: palette.hover_background.a * ICON_BACKING_ALPHA,
+            ..palette.hover_background
+        }),
+        IconBacking::Hover => None,
+    };
+    let border = if hovered || needs_attention {
+        let border_color = if needs_attention { palette.attention } else { palette.hover_border };
+        Border {
+            radius: 8.0.into(),
+            width: 1.0,
+            color: border_color,
+        }
+    } else {
+        Border {
+            radius: 8.0.into(),
+            ..Default::default()
+        }
+    };
+    container::Style {
+        background: background.map(Background::Color),
+        border,
+        ..Default::default()
+    }
+}
+
+impl TrayItem {
+    // An item with no properties yet; set_properties fills them in
+    fn new(opacity: f32) -> Self {
+        TrayItem {
+            id: String::new(),
+            title: None,
+            icon: None,
+            icon_data: IconData::default(),
+            status: Status::default(),
+            category: Category::default(),
+            attention_icon: None,
+            attention_icon_data: IconData::default(),
+            overlay_icon: None,
+            overlay_icon_data: IconData::default(),
+            hovered: false,
+            hover_exit_at: None,
+            scroll_accum: (0.0, 0.0),
+            hover_started: None,
+            tooltip: None,
+            menu_path: None,
+            menu: None,
+            opacity,
+            added_at: Instant::now(),
+            last_icon_update: None,
+            icon_frames: VecDeque::new(),
+        }
+    }
+
+    // Takes every property the app reports, leaving UI state alone. Returns
+    // the icon slots whose source changed; their old handles stay up until
+    // IconResolved swaps in the new ones, so a re-Add doesn't flash the
+    // placeholder.
+    fn set_properties(&mut self, item: ItemData) -> Vec<IconSlot> {
+        let mut stale = Vec::new();
+        let slots = [
+            (IconSlot::Main, item.icon, &mut self.icon_data),
+            (IconSlot::Attention, item.attention_icon, &mut self.attention_icon_data),
+            (IconSlot::Overlay, item.overlay_icon, &mut self.overlay_icon_data),
+        ];
+        for (slot, icon, data) in slots {
+            if !same_icon_data(data, &icon) {
+                *data = icon;
+                stale.push(slot);
+            }
+        }
+        self.id = item.id;
+        self.title = item.title;
+        self.status = item.status;
+        self.category = item.category;
+        self.tooltip = item.tooltip;
+        self.menu_path = item.menu_path;
+        self.menu = item.menu;
+        stale
+    }
+
+    fn icon_slot(&self, slot: IconSlot) -> (&IconData, &Option<IconHandle>) {
+        match slot {
+            IconSlot::Main => (&self.icon_data, &self.icon),
+            IconSlot::Attention => (&self.attention_icon_data, &self.attention_icon),
+            IconSlot::Overlay => (&self.overlay_icon_data, &self.overlay_icon),
+        }
+    }
+
+    fn needs_attention(&self) -> bool {
+        matches!(self.status, Status::NeedsAttention)
+    }
+
+    // The attention icon while the item needs attention, otherwise the normal
+    // one; the built-in placeholder if neither resolved, so the item stays clickable
+    fn displayed_icon(&self) -> &IconHandle {
+        let icon = if self.needs_attention() {
+            self.attention_icon.as_ref().or(self.icon.as_ref())
+        } else {
+            self.icon.as_ref()
+        };
+        icon.unwrap_or(&FALLBACK_ICON)
+    }
+}
+
+fn view(state: &State, window_id: window::Id) -> Element<'_, Message> {
+    // Popups render by the kind recorded when they were opened; every other
+    // window is a bar, even before its first size arrives
+    match state.popup_windows.get(&window_id).map(|(kind, _)| kind) {
+        Some(PopupKind::Menu) => return animated_popup(state, window_id, view_menu_popup),
+        Some(PopupKind::Tooltip) => return view_tooltip_popup(state),
+        Some(PopupKind::Overflow) => return animated_popup(state, window_id, view_overflow_popup),
+        None => {}
+    }
+
+    // Bar view; outputs differ only in their [outputs."NAME"] sections
+    let bar = bar_config(state, window_id);
+    let vertical = state.config.is_vertical();
+    let icon_size = bar.icon_size;
+    let container_size = bar.container_size;
+    let palette = state.palette;
+    let symbolic_color = palette.symbolic_color;
+    let focused = state
+        .focused_icon
+        .and_then(|index| focusable_addresses(state).get(index).copied());
+    let visible = visible_items(state);
+    let mut tray_icons: Vec<Element<'_, Message>> = Vec::with_capacity(visible.len());
+    for (index, (address, item)) in visible.iter().copied().enumerate() {
+        if state.config.group_by_category
+            && index > 0
+            && visible[index - 1].1.category != item.category
+        {
+            tray_icons.push(category_separator(vertical, container_size, palette.menu_border));
+        }
+        let handle = item.displayed_icon();
+        let opacity = if state.config.dim_passive_items && item.status == Status::Passive {
+            item.opacity * PASSIVE_OPACITY
+        } else {
+            item.opacity
+        };
+        let base = icon_element(handle, icon_size, opacity, symbolic_color);
+        let icon_widget: Element<'_, Message> = match &item.overlay_icon {
+            // Badge pinned to the bottom-right corner of the base icon
+            Some(overlay) => {
+                let badge = icon_element(overlay, icon_size * OVERLAY_SCALE, opacity, symbolic_color);
+                stack![
+                    base,
+                    container(badge).align_right(Length::Fixed(icon_size)).align_bottom(Length::Fixed(icon_size)),
+                ]
+                .into()
+            }
+            None => base,
+        };
+
+        // Keyboard focus looks the same as pointer hover
+        let hovered = item.hovered || focused == Some(address);
+        let needs_attention = item.needs_attention();
+        let backing = state.config.icon_backing;
+        let addr = address.clone();
+        let addr2 = address.clone();
+        let addr3 = address.clone();
+        let addr4 = address.clone();
+        let addr5 = address.clone();
+        let addr6 = address.clone();
+
+        tray_icons.push(
+            mouse_area(
+                container(icon_widget)
+                    .width(Length::Fixed(container_size))
+                    .height(Length::Fixed(container_size))
+                    .center_x(Length::Fixed(container_size))
+                    .center_y(Length::Fixed(container_size))
+                    .style(move |_| tray_icon_container_style(&palette, backing, hovered, needs_attention)),
+            )
+            .on_press(Message::TrayIconClicked(addr, ClickType::Left))
+            .on_right_press(Message::TrayIconClicked(addr2, ClickType::Right))
+            .on_middle_press(Message::TrayIconClicked(addr3, ClickType::Middle))
+            .on_enter(Message::TrayIconHover(addr4, true))
+            .on_exit(Message::TrayIconHover(addr5, false))
+            .on_scroll(move |delta| Message::TrayIconScroll(addr6.clone(), delta))
+            .into(),
+        );
+    }
+
+    // The chevron leads the tray and only appears when it has something to reveal
+    let has_collapsible = ordered_items(state)
+        .iter()
+        .any(|(address, item)| collapsible_item(state, address, item));
+    if state.config.collapsible && has_collapsible {
+        let chevron = if state.tray_expanded { "›" } else { "‹" };
+        tray_icons.insert(
+            0,
+            mouse_area(
+                container(iced::widget::text(chevron).size(14).color(palette.text))
+                    .center_x(Length::Fixed(container_size))
+                    .center_y(Length::Fixed(container_size)),
+            )
+            .on_press(Message::ToggleTrayOverflow)
+            .into(),
+        );
+    }
+
+    // Icons past config.max_visible_icons sit behind a "+K" indicator at the end
+    let hidden = overflow_items(state).len();
+    if hidden > 0 {
+        tray_icons.push(
+            mouse_area(
+                container(iced::widget::text(format!("+{hidden}")).size(12).color(palette.text))
+                    .center_x(Length::Fixed(container_size))
+                    .center_y(Length::Fixed(container_size)),
+            )
+            .on_press(Message::OverflowIndicatorClicked)
+            .into(),
+        );
+    }
+
+    let tray_row: Element<'_, Message> = if !bar.show_tray || !state.initial_items_ready {
+        // Hidden on this output, or waiting so the whole startup set appears at once
+        Space::new().into()
+    } else if state.tray_items.is_empty() && state.config.show_empty_message {
+        let hint = if state.tray_connected {
+            &state.config.empty_message
+        } else {
+            &state.config.disconnected_message
+        };
+        iced::widget::text(hint.as_str()).size(12).color(palette.text_disabled).into()
+    } else {
+        // Scrolls along the bar when there are more icons than room. Popups are
+        // placed from window coordinates, so the scroll offset doesn't matter there.
+        let direction = if vertical {
+            scrollable::Direction::Vertical(tray_scrollbar())
+        } else {
+            scrollable::Direction::Horizontal(tray_scrollbar())
+        };
+        scrollable(bar_stack(vertical, tray_icons, state.config.tray_spacing))
+            .direction(direction)
+            .into()
+    };
+
+    let clock: Element<'_, Message> = if !bar.show_clock {
+        Space::new().into()
+    } else if vertical {
+        // Too narrow for a line of text: stack the clock's fields, one per line
+        let fields = state
+            .clock_text
+            .split([' ', ':'])
+            .filter(|field| !field.is_empty())
+            .map(|field| iced::widget::text(field).size(13).color(palette.text).into())
+            .collect();
+        bar_stack(true, fields, 0.0)
+    } else {
+        iced::widget::text(state.clock_text.as_str()).size(13).color(palette.text).into()
+    };
+
+    // Three segments separated by flexible space, each module in the one its
+    // config names. On a vertical bar "left" is the top and "right" the bottom.
+    let gap = |size: f32| bar_gap(vertical, Length::Fixed(size));
+    let mut segments: [Vec<Element<'_, Message>>; 3] = Default::default();
+    for (segment, module) in [(state.config.clock_position, clock), (state.config.tray_position, tray_row)] {
+        let segment = &mut segments[segment as usize];
+        if !segment.is_empty() {
+            segment.push(gap(24.0));
+        }
+        segment.push(module);
+    }
+    let [mut left, center, mut right] = segments;
+    // Keep the outermost modules off the screen edges
+    if !left.is_empty() {
+        left.insert(0, gap(state.config.edge_margin));
+    }
+    if !right.is_empty() {
+        right.push(gap(state.config.edge_margin));
+    }
+    let [left, center, right] = [left, center, right].map(|children| bar_stack(vertical, children, 0.0));
+
+    // The app background is transparent (see `style`), so a translucent bar lets
+    // the compositor show (and blur) whatever is behind it.
+    let bar_background = Color {
+        a: palette.bar_background.a * state.config.background_opacity.clamp(0.0, 1.0),
+        ..palette.bar_background
+    };
+
+    // Corners outside the radius stay transparent, as the surface itself is
+    let bar_border = Border {
+        radius: state.config.bar_corner_radius.max(0.0).into(),
+        width: state.config.bar_border.width.max(0.0),
+        color: state.config.bar_border.color.unwrap_or(palette.menu_border),
+    };
+
+    // Icons capture their own scroll and presses, so this only sees the empty parts of the bar
+    let bar = mouse_area(
+        container(bar_stack(
+            vertical,
+            vec![left, bar_gap(vertical, Length::Fill), center, bar_gap(vertical, Length::Fill), right],
+            0.0,
+        ))
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .style(move |_| container::Style {
+            background: Some(bar_background.into()),
+            border: bar_border,
+            ..Default::default()
+        }),
+    )
+    .on_scroll(Message::BarScroll);
+    // Without a command, presses stay uncaptured and reach PointerPressed
+    match state.config.on_bar_click_command {
+        Some(_) => bar.on_press(Message::BarClicked(window_id)).into(),
+        None => bar.into(),
+    }
+}
+
+// Hairline between category groups, a bit shorter than the icons
+fn category_separator<'a>(vertical: bool, container_size: f32, color: Color) -> Element<'a, Message> {
+    let length = Length::Fixed((container_size * 0.6).round());
+    let line = container(Space::new())
+        .width(if vertical { length } else { Length::Fixed(1.0) })
+        .height(if vertical { Length::Fixed(1.0) } else { length })
+        .style(move |_| container::Style {
+            background: Some(color.into()),
+            ..Default::default()
+        });
+    if vertical {
+        container(line).center_x(Length::Fixed(container_size)).into()
+    } else {
+        container(line).center_y(Length::Fixed(container_size)).into()
+    }
+}
+
+fn icon_element<'a>(handle: &IconHandle, size: f32, opacity: f32, symbolic_color: Color) -> Element<'a, Message> {
+    match handle {
+        IconHandle::Raster(h) => image(h.clone())
+            .width(Length::Fixed(size))
+            .height(Length::Fixed(size))
+            .opacity(opacity)
+            .into(),
+        IconHandle::Svg(h) => svg(h.clone())
+            .width(Length::Fixed(size))
+            .height(Length::Fixed(size))
+            .opacity(opacity)
+            .into(),
+        // Symbolic art is drawn in a placeholder color meant to be replaced
+        IconHandle::Symbolic(h) => svg(h.clone())
+            .width(Length::Fixed(size))
+            .height(Length::Fixed(size))
+            .opacity(opacity)
+            .style(move |_, _| svg::Style { color: Some(symbolic_color) })
+            .into(),
+    }
+}
+
+// Addresses of the icons drawn, in bar order; focused_icon indexes this
+fn focusable_addresses(state: &State) -> Vec<&String> {
+    visible_items(state).into_iter().map(|(address, _)| address).collect()
+}
+
+// Icons drawn on the bar: ordered_items minus those folded behind the
+// chevron or cut off by config.max_visible_icons
+fn visible_items(state: &State) -> Vec<(&String, &TrayItem)> {
+    split_overflow(state).0
+}
+
+// Icons behind the "+K" indicator, in bar order
+fn overflow_items(state: &State) -> Vec<(&String, &TrayItem)> {
+    split_overflow(state).1
+}
+
+type AddressedItem<'a> = (&'a String, &'a TrayItem);
+
+// (shown, overflowing). Cycling rotates the list so each click brings the
+// next batch of hidden icons into the visible slots.
+fn split_overflow(state: &State) -> (Vec<AddressedItem<'_>>, Vec<AddressedItem<'_>>) {
+    let mut items = ordered_items(state);
+    if state.config.collapsible && !state.tray_expanded {
+        items.retain(|(address, item)| !collapsible_item(state, address, item));
+    }
+    let Some(max) = state.config.max_visible_icons.filter(|&max| items.len() > max) else {
+        return (items, Vec::new());
+    };
+    if state.config.overflow_action == OverflowAction::Cycle {
+        let len = items.len();
+        items.rotate_left(state.overflow_offset % len);
+    }
+    let hidden = items.split_off(max);
+    (items, hidden)
+}
+
+// Items that config.always_shown doesn't match go behind the chevron, unless
+// they currently need attention
+fn collapsible_item(state: &State, address: &str, item: &TrayItem) -> bool {
+    let title = item.title.as_deref().unwrap_or("");
+    let always_shown = state.config.always_shown.iter().any(|pattern| {
+        glob_match(pattern, &item.id)
+            || (!title.is_empty() && glob_match(pattern, title))
+            || glob_match(pattern, address)
+    });
+    !always_shown && !item.needs_attention()
+}
+
+// Tray items in display order: config.icon_order pins first, then config.sort;
+// ties keep insertion order
+fn ordered_items(state: &State) -> Vec<(&String, &TrayItem)> {
+    let mut items: Vec<(&String, &TrayItem)> = state
+        .item_order
+        .iter()
+        .filter_map(|address| state.tray_items.get(address).map(|item| (address, item)))
+        .collect();
+
+    match state.config.sort {
+        IconSort::Insertion => {}
+        IconSort::Alphabetical => items.sort_by_cached_key(|(_, item)| item.id.to_lowercase()),
+        IconSort::Title => items.sort_by_cached_key(|(_, item)| {
+            item.title.as_deref().filter(|t| !t.is_empty()).unwrap_or(&item.id).to_lowercase()
+        }),
+    }
+    if !state.config.icon_order.is_empty() {
+        items.sort_by_cached_key(|(address, item)| pinned_slot(&state.config.icon_order, address, item));
+    }
+    // Groups are outermost; icon_order and sort still apply within each
+    if state.config.group_by_category {
+        items.sort_by_key(|(_, item)| category_rank(&state.config.category_order, item.category));
+    }
+    items
+}
+
+fn category_rank(category_order: &[Category], category: Category) -> usize {
+    category_order
+        .iter()
+        .position(|&c| c == category)
+        .unwrap_or(category_order.len())
+}
+
+// Position of an item among icon_order: the first pattern matching its SNI id,
+// title or bus name. Unmatched items go where "..." is, or after every pattern.
+fn pinned_slot(icon_order: &[String], address: &str, item: &TrayItem) -> usize {
+    let title = item.title.as_deref().unwrap_or("");
+    icon_order
+        .iter()
+        .position(|pattern| {
+            pattern != UNPINNED_SLOT
+                && (glob_match(pattern, &item.id)
+                    || (!title.is_empty() && glob_match(pattern, title))
+                    || glob_match(pattern, address))
+        })
+        .or_else(|| icon_order.iter().position(|pattern| pattern == UNPINNED_SLOT))
+        .unwrap_or(icon_order.len())
+}
+
+// Thin and drawn over the icons, so it doesn't add to the bar's thickness
+fn tray_scrollbar() -> scrollable::Scrollbar {
+    scrollable::Scrollbar::new().width(2).scroller_width(2).margin(0)
+}
+
+// Lays children out along the bar: a row, or a column on a vertical bar
+fn bar_stack<'a>(vertical: bool, children: Vec<Element<'a, Message>>, spacing: f32) -> Element<'a, Message> {
+    if vertical {
+        iced::widget::column(children)
+            .spacing(spacing)
+            .align_x(iced::Alignment::Center)
+            .into()
+    } else {
+        row(children).spacing(spacing).align_y(iced::Alignment::Center).into()
+    }
+}
+
+// Empty space of the given length along the bar
+fn bar_gap<'a>(vertical: bool, length: Length) -> Element<'a, Message> {
+    if vertical {
+        Space::new().height(length).into()
+    } else {
+        Space::new().width(length).into()
+    }
+}
+
+// Menu layout metrics (also used to size the popup window)
+const MENU_PADDING: f32 = 6.0;
+const MENU_CORNER_RADIUS: f32 = 8.0; // Unless config.popup_corner_radius is set
+const MENU_ROW_HEIGHT: f32 = 24.0;
+const MENU_SEPARATOR_HEIGHT: f32 = 7.0;
+const MENU_INDENT: f32 = 12.0;
+const MENU_TOGGLE_WIDTH: f32 = 18.0; // Mark column for checkmark and radio items
+const MENU_EMPTY_HEIGHT: i32 = 40;
+const MENU_CHAR_WIDTH: f32 = 7.0; // Rough average glyph advance at size 12
+const MENU_MIN_WIDTH: i32 = 120;
+const MENU_MAX_WIDTH: i32 = 360;
+const OVERFLOW_ICON_SIZE: f32 = 16.0; // Icons in the overflow popup's rows
+
+// Draws a menu-like popup partway through its transition: the view fades
+// its colors by the progress, and the panel stops short of its far edge by
+// the remaining slide
+fn animated_popup<'a>(
+    state: &'a State,
+    id: window::Id,
+    view_popup: fn(&'a State, window::Id, f32) -> Element<'a, Message>,
+) -> Element<'a, Message> {
+    let progress = popup_progress(state, id);
+    let content = view_popup(state, id, progress);
+    if progress >= 1.0 {
+        return content;
+    }
+    // Popups open away from the bar, so the far edge is the one that moves
+    let slide = (1.0 - progress) * POPUP_SLIDE;
+    let padding = if state.config.edge() == BarPosition::Bottom {
+        iced::Padding { top: slide, ..Default::default() }
+    } else {
+        iced::Padding { bottom: slide, ..Default::default() }
+    };
+    container(content).padding(padding).into()
+}
+
+fn view_overflow_popup(state: &State, _id: window::Id, progress: f32) -> Element<'_, Message> {
+    use iced::widget::{button, column, text};
+
+    let palette = state.palette.faded(progress);
+    let entries: Vec<Element<'_, Message>> = overflow_items(state)
+        .into_iter()
+        .map(|(address, item)| {
+            let icon = icon_element(item.displayed_icon(), OVERFLOW_ICON_SIZE, progress, palette.symbolic_color);
+            let label = row![icon, text(item_label(item)).size(12).color(palette.text)]
+                .spacing(8)
+                .align_y(iced::Alignment::Center);
+            button(label)
+                .width(Length::Fill)
+                .height(Length::Fixed(MENU_ROW_HEIGHT))
+                .padding([4, 8])
+                .style(move |theme, status| menu_item_style(theme, status, &palette))
+                .on_press(Message::OverflowItemClicked(address.clone()))
+                .into()
+        })
+        .collect();
+
+    let style = popup_style(&state.config, &palette, MENU_CORNER_RADIUS);
+    container(column(entries).padding(MENU_PADDING))
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .style(move |_| style)
+        .into()
+}
+
+// Background and outline shared by every popup. Compositors that mishandle
+// per-surface alpha can get square, opaque popups via the config.
+fn popup_style(config: &Config, palette: &Palette, default_radius: f32) -> container::Style {
+    container::Style {
+        background: Some(Background::Color(Color {
+            a: palette.menu_background.a * config.popup_opacity.clamp(0.0, 1.0),
+            ..palette.menu_background
+        })),
+        border: Border {
+            radius: config.popup_corner_radius.unwrap_or(default_radius).max(0.0).into(),
+            width: 1.0,
+            color: palette.menu_border,
+        },
+        ..Default::default()
+    }
+}
+
+// Title for display, falling back to the SNI id
+fn item_label(item: &TrayItem) -> &str {
+    item.title.as_deref().filter(|t| !t.is_empty()).unwrap_or(&item.id)
+}
+
+fn view_menu_popup(state: &State, id: window::Id, progress: f32) -> Element<'_, Message> {
+    use iced::widget::{column, text};
+
+    let palette = state.palette.faded(progress);
+
+    // A closing menu keeps showing the item it was opened for
+    let closing = state.closing_popups.get(&id);
+    let address = match closing {
+        Some(closing) => closing.address.as_ref(),
+        None => state.popup_for_address.as_ref(),
+    };
+    let menu = address
+        .and_then(|a| state.tray_items.get(a))
+        .and_then(|item| item.menu.as_ref());
+
+    let highlighted = state
+        .menu_highlight
+        .filter(|_| closing.is_none())
+        .and_then(|index| popup_menu_ids(state).get(index).copied());
+
+    let mut entries: Vec<Element<'_, Message>> = Vec::new();
+    if let Some(address) = address {
+        let bold = iced::Font {
+            weight: iced::font::Weight::Bold,
+            ..iced::Font::DEFAULT
+        };
+        entries.push(
+            container(text(menu_header(state, address)).size(12).font(bold).color(palette.text))
+                .height(Length::Fixed(MENU_ROW_HEIGHT))
+                .padding([4, 8])
+                .into(),
+        );
+        entries.push(Space::new().height(Length::Fixed(MENU_SEPARATOR_HEIGHT)).into());
+    }
+    match menu {
+        Some(menu) if !menu.submenus.is_empty() => {
+            push_menu_entries(&menu.submenus, 0, highlighted, palette, &mut entries);
+        }
+        _ => entries.push(text("No menu").size(12).color(palette.text_disabled).into()),
+    }
+
+    // Single container fills the window with rounded corners
+    // The transparent app background allows corners to show through
+    let style = popup_style(&state.config, &palette, MENU_CORNER_RADIUS);
+    container(column(entries).padding(MENU_PADDING))
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .style(move |_| style)
+        .into()
+}
+
+// Flattens the dbusmenu tree into popup rows; submenus are rendered inline
+// below their parent, indented one level per depth
+fn push_menu_entries<'a>(
+    items: &'a [MenuItem],
+    depth: usize,
+    highlighted: Option<i32>,
+    palette: Palette,
+    out: &mut Vec<Element<'a, Message>>,
+) {
+    use iced::widget::{button, text};
+
+    // Siblings share a mark column once any of them can be toggled
+    let toggle_column = has_toggles(items);
+    for item in items.iter().filter(|i| i.visible) {
+        if matches!(item.menu_type, MenuType::Separator) {
+            out.push(
+                Space::new()
+                    .width(Length::Fill)
+                    .height(Length::Fixed(MENU_SEPARATOR_HEIGHT))
+                    .into(),
+            );
+            continue;
+        }
+
+        let has_submenu = !item.submenu.is_empty();
+        let is_highlighted = highlighted == Some(item.id);
+        let color = if item.enabled { palette.text } else { palette.text_disabled };
+
+        let mut label = row![].align_y(iced::Alignment::Center);
+        if toggle_column {
+            label = label.push(text(toggle_mark(item)).size(12).color(color).width(Length::Fixed(MENU_TOGGLE_WIDTH)));
+        }
+        let label = label
+            .push(text(menu_label(item)).size(12).color(color))
+            .push(Space::new().width(Length::Fill))
+            .push(text(if has_submenu { "›" } else { "" }).size(12).color(color));
+
+        let mut entry = button(label)
+            .width(Length::Fill)
+            .height(Length::Fixed(MENU_ROW_HEIGHT))
+            .padding(iced::Padding {
+                top: 4.0,
+                bottom: 4.0,
+                left: 8.0 + depth as f32 * MENU_INDENT,
+                right: 8.0,
+            })
+            .style(move |theme, status| {
+                // The keyboard highlight looks the same as pointer hover
+                let status = if is_highlighted { iced::widget::button::Status::Hovered } else { status };
+                menu_item_style(theme, status, &palette)
+            });
+        // Submenu parents are headers only; their children follow indented
+        if item.enabled && !has_submenu {
+            entry = entry.on_press(Message::MenuItemClicked(item.id));
+        }
+        out.push(entry.into());
+
+        if has_submenu {
+            push_menu_entries(&item.submenu, depth + 1, highlighted, palette, out);
+        }
+    }
+}
+
+fn has_toggles(items: &[MenuItem]) -> bool {
+    items
+        .iter()
+        .any(|i| i.visible && i.toggle_type != ToggleType::CannotBeToggled)
+}
+
+// Check or radio mark for toggle items; blank for unset and plain entries
+fn toggle_mark(item: &MenuItem) -> &'static str {
+    match (item.toggle_type, item.toggle_state) {
+        (ToggleType::CannotBeToggled, _) | (ToggleType::Checkmark, ToggleState::Off) => "",
+        (ToggleType::Checkmark, ToggleState::On) => "✓",
+        (ToggleType::Radio, ToggleState::On) => "●",
+        (ToggleType::Radio, ToggleState::Off) => "○",
+        (_, ToggleState::Indeterminate) => "–",
+    }
+}
+
+// Ids of the clickable rows in the open popup, in the order push_menu_entries renders them
+fn popup_menu_ids(state: &State) -> Vec<i32> {
+    fn collect(items: &[MenuItem], out: &mut Vec<i32>) {
+        for item in items.iter().filter(|i| i.visible) {
+            if matches!(item.menu_type, MenuType::Separator) {
+                continue;
+            }
+            if !item.submenu.is_empty() {
+                collect(&item.submenu, out);
+            } else if item.enabled {
+                out.push(item.id);
+            }
+        }
+    }
+
+    let mut ids = Vec::new();
+    if let Some(menu) = state
+        .popup_for_address
+        .as_ref()
+        .and_then(|a| state.tray_items.get(a))
+        .and_then(|item| item.menu.as_ref())
+    {
+        collect(&menu.submenus, &mut ids);
+    }
+    ids
+}
+
+// dbusmenu labels mark access keys with '_' and escape literal underscores as "__"
+fn menu_label(item: &MenuItem) -> String {
+    let raw = item.label.as_deref().unwrap_or("");
+    let mut label = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '_' {
+            if chars.peek() == Some(&'_') {
+                chars.next();
+                label.push('_');
+            }
+        } else {
+            label.push(c);
+        }
+    }
+    label
+}
+
+fn menu_item_style(_theme: &Theme, status: iced::widget::button::Status, palette: &Palette) -> iced::widget::button::Style {
+    use iced::widget::button::{Status, Style};
+
+    let background = match status {
+        Status::Hovered | Status::Pressed => Some(Background::Color(palette.menu_hover)),
+        Status::Active | Status::Disabled => None,
+    };
+    Style {
+        background,
+        text_color: palette.text,
+        border: Border {
+            radius: 4.0.into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+// The item's title, else its SNI id, else the bus address; cut to fit MENU_MAX_WIDTH
+fn menu_header(state: &State, address: &str) -> String {
+    let label = state.tray_items.get(address).map_or(address, item_label);
+    let max_chars = ((MENU_MAX_WIDTH as f32 - 16.0 - MENU_PADDING * 2.0) / MENU_CHAR_WIDTH) as usize;
+    if label.chars().count() <= max_chars {
+        return label.to_string();
+    }
+    let mut cut: String = label.chars().take(max_chars - 1).collect();
+    cut.push('…');
+    cut
+}
+
+// Popup (width, height) needed to show every visible row of the menu
+fn menu_popup_size(header: &str, items: &[MenuItem]) -> (i32, i32) {
+    fn rows_height(items: &[MenuItem]) -> f32 {
+        items
+            .iter()
+            .filter(|i| i.visible)
+            .map(|i| match i.menu_type {
+                MenuType::Separator => MENU_SEPARATOR_HEIGHT,
+                _ => MENU_ROW_HEIGHT + rows_height(&i.submenu),
+            })
+            .sum()
+    }
+
+    // Widest row: indent, toggle marks, label, and the submenu marker, plus the row's own padding
+    fn rows_width(items: &[MenuItem], depth: usize) -> f32 {
+        let toggles = if has_toggles(items) { MENU_TOGGLE_WIDTH } else { 0.0 };
+        items
+            .iter()
+            .filter(|i| i.visible && !matches!(i.menu_type, MenuType::Separator))
+            .map(|i| {
+                let label = menu_label(i).chars().count() as f32 * MENU_CHAR_WIDTH;
+                let marker = if i.submenu.is_empty() { 0.0 } else { MENU_CHAR_WIDTH * 2.0 };
+                let row = 16.0 + depth as f32 * MENU_INDENT + toggles + label + marker;
+                row.max(rows_width(&i.submenu, depth + 1))
+            })
+            .fold(0.0, f32::max)
+    }
+
+    // The header is a title row plus a separator-sized gap
+    let header_width = 16.0 + header.chars().count() as f32 * MENU_CHAR_WIDTH;
+    let header_height = MENU_ROW_HEIGHT + MENU_SEPARATOR_HEIGHT;
+
+    let height = rows_height(items);
+    if height == 0.0 {
+        let width = (header_width + MENU_PADDING * 2.0).ceil() as i32;
+        return (width.clamp(MENU_MIN_WIDTH, MENU_MAX_WIDTH), MENU_EMPTY_HEIGHT + header_height as i32);
+    }
+    let width = (rows_width(items, 0).max(header_width) + MENU_PADDING * 2.0).ceil() as i32;
+    (
+        width.clamp(MENU_MIN_WIDTH, MENU_MAX_WIDTH),
+        (header_height + height + MENU_PADDING * 2.0).ceil() as i32,
+    )
+}
+
+// Tooltip layout metrics (also used to size the tooltip window)
+const TOOLTIP_PADDING: f32 = 8.0;
+const TOOLTIP_CORNER_RADIUS: f32 = 6.0; // Unless config.popup_corner_radius is set
+const TOOLTIP_LINE_HEIGHT: f32 = 16.0;
+const TOOLTIP_CHAR_WIDTH: f32 = 7.0; // Rough average glyph advance at size 12
+const TOOLTIP_MIN_WIDTH: f32 = 60.0;
+const TOOLTIP_MAX_WIDTH: f32 = 320.0;
+
+fn view_tooltip_popup(state: &State) -> Element<'_, Message> {
+    use iced::widget::{column, text};
+
+    let tooltip = state
+        .tooltip_popup
+        .as_ref()
+        .and_then(|(_, address)| state.tray_items.get(address))
+        .and_then(|item| feedback_text(&state.config, item));
+
+    let palette = state.palette;
+    let mut lines: Vec<Element<'_, Message>> = Vec::new();
+    if let Some(tooltip) = tooltip {
+        if !tooltip.title.is_empty() {
+            let bold = iced::Font {
+                weight: iced::font::Weight::Bold,
+                ..iced::Font::DEFAULT
+            };
+            lines.push(text(tooltip.title).size(12).font(bold).color(palette.text).into());
+        }
+        if !tooltip.body.is_empty() {
+            lines.push(text(tooltip.body).size(12).color(palette.text).into());
+        }
+    }
+
+    let style = popup_style(&state.config, &palette, TOOLTIP_CORNER_RADIUS);
+    container(column(lines).padding(TOOLTIP_PADDING))
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .style(move |_| style)
+        .into()
+}
+
+// Estimates the tooltip window size from its text, wrapping at TOOLTIP_MAX_WIDTH
+fn tooltip_size(tooltip: &TooltipText) -> (u32, u32) {
+    let lines = || tooltip.title.lines().chain(tooltip.body.lines());
+
+    let longest = lines().map(|l| l.chars().count()).max().unwrap_or(0);
+    let width = (longest as f32 * TOOLTIP_CHAR_WIDTH + TOOLTIP_PADDING * 2.0)
+        .clamp(TOOLTIP_MIN_WIDTH, TOOLTIP_MAX_WIDTH);
+
+    let chars_per_line = (((width - TOOLTIP_PADDING * 2.0) / TOOLTIP_CHAR_WIDTH) as usize).max(1);
+    let wrapped: usize = lines()
+        .map(|l| l.chars().count().div_ceil(chars_per_line).max(1))
+        .sum();
+    let height = wrapped as f32 * TOOLTIP_LINE_HEIGHT + TOOLTIP_PADDING * 2.0;
+
+    (width.ceil() as u32, height.ceil() as u32)
+}
+
+fn theme(state: &State, _window_id: window::Id) -> Theme {
+    if state.palette.is_dark() { Theme::Dark } else { Theme::Light }
+}
+
+// App style with transparent background (allows rounded corners on popups)
+fn style(_state: &State, theme: &Theme) -> iced::theme::Style {
+    iced::theme::Style {
+        background_color: Color::TRANSPARENT,
+        text_color: theme.palette().text,
+    }
+}
+
+
+// Reads org.freedesktop.appearance color-scheme from the settings portal, then
+// follows its SettingChanged signal. Emits nothing if there is no portal, which
+// leaves the dark palette in place.
+fn color_scheme_subscription() -> impl iced::futures::Stream<Item = Message> {
+    use iced::futures::StreamExt;
+
+    iced::futures::stream::once(async {
+        let proxy = match settings_portal().await {
+            Ok(proxy) => proxy,
+            Err(e) => {
+                tracing::debug!(error = %e, "Settings portal unavailable, keeping the dark palette");
+                return iced::futures::stream::empty().boxed();
+            }
+        };
+        let initial = read_color_scheme(&proxy).await;
+        let changes = match proxy.receive_signal("SettingChanged").await {
+            Ok(signals) => signals
+                .filter_map(|signal| async move {
+                    let (namespace, key, value): (String, String, zbus::zvariant::OwnedValue) =
+                        signal.body().deserialize().ok()?;
+                    (namespace == "org.freedesktop.appearance" && key == "color-scheme")
+                        .then(|| color_scheme_is_dark(&value))
+                })
+                .boxed(),
+            Err(e) => {
+                tracing::debug!(error = %e, "Can't watch the color scheme for changes");
+                iced::futures::stream::empty().boxed()
+            }
+        };
+        iced::futures::stream::iter(initial).chain(changes).boxed()
+    })
+    .flatten()
+    .map(Message::ColorSchemeChanged)
+}
+
+async fn settings_portal() -> zbus::Result<zbus::Proxy<'static>> {
+    zbus::proxy::Builder::new(session_bus().await?)
+        .destination("org.freedesktop.portal.Desktop")?
+        .path("/org/freedesktop/portal/desktop")?
+        .interface("org.freedesktop.portal.Settings")?
+        .build()
+        .await
+}
+
+// None when the portal can't tell us
+async fn read_color_scheme(proxy: &zbus::Proxy<'_>) -> Option<bool> {
+    let key = ("org.freedesktop.appearance", "color-scheme");
+    // ReadOne is the current API; older portals only have the deprecated Read
+    let value: zbus::zvariant::OwnedValue = match proxy.call("ReadOne", &key).await {
+        Ok(value) => value,
+        Err(_) => proxy
+            .call("Read", &key)
+            .await
+            .inspect_err(|e| tracing::debug!(error = %e, "Failed to read color-scheme"))
+            .ok()?,
+    };
+    Some(color_scheme_is_dark(&value))
+}
+
+// 0 = no preference, 1 = prefer dark, 2 = prefer light. No preference keeps
+// the stock dark look.
+fn color_scheme_is_dark(value: &zbus::zvariant::Value<'_>) -> bool {
+    match value {
+        // Read wraps the value in an extra variant
+        zbus::zvariant::Value::Value(inner) => color_scheme_is_dark(inner),
+        zbus::zvariant::Value::U32(scheme) => *scheme != 2,
+        _ => true,
+    }
+}
+
+// One session bus connection shared by every click, scroll and menu call
+async fn session_bus() -> zbus::Result<&'static Connection> {
+    SESSION_BUS.get_or_try_init(Connection::session).await
+}
+
+// Cached full address for a bus name, falling back to asking the watcher
+async fn resolve_sni_address(bus_name: &str) -> zbus::Result<String> {
+    if let Some(full_address) = SNI_ADDRESSES.lock().unwrap().get(bus_name) {
+        return Ok(full_address.clone());
+    }
+    let full_address = lookup_full_sni_address(bus_name).await?;
+    SNI_ADDRESSES.lock().unwrap().insert(bus_name.to_string(), full_address.clone());
+    Ok(full_address)
+}
+
+// Resolves the address as soon as an item appears, so its first click is fast
+fn prefetch_sni_address(bus_name: String) {
+    tokio::spawn(async move {
+        if let Err(e) = resolve_sni_address(&bus_name).await {
+            tracing::debug!(address = %bus_name, error = %e, "Failed to resolve SNI address");
+        }
+    });
+}
 
 async fn lookup_full_sni_address(bus_name: &str) -> zbus::Result<String> {
-    let conn = Connection::session().await?;
-    let proxy: zbus::Proxy<'_> = zbus::proxy::Builder::new(&conn)
+    let proxy: zbus::Proxy<'_> = zbus::proxy::Builder::new(session_bus().await?)
         .destination("org.kde.StatusNotifierWatcher")?
         .path("/StatusNotifierWatcher")?
         .interface("org.kde.StatusNotifierWatcher")?
@@ -471,69 +2832,303 @@ async fn lookup_full_sni_address(bus_name: &str) -> zbus::Result<String> {
 }
 
 fn parse_sni_address(address: &str) -> (&str, String) {
-    // Address can be ":1.58" or ":1.58/org/blueman/sni"
-    address
-        .split_once('/')
-        .map_or((address, String::from("/StatusNotifierItem")), |(d, p)| {
-            (d, format!("/{p}"))
-        })
+    // Address can be ":1.58" or ":1.58/org/blueman/sni". Empty segments
+    // (":1.58/", "a//b") are dropped so the path is always a valid object path.
+    let (dest, path) = address.split_once('/').unwrap_or((address, ""));
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    if segments.is_empty() {
+        (dest, String::from("/StatusNotifierItem"))
+    } else {
+        (dest, format!("/{}", segments.join("/")))
+    }
 }
 
-async fn sni_activate(bus_name: &str, x: i32, y: i32) -> zbus::Result<()> {
-    let full_address = lookup_full_sni_address(bus_name).await?;
+// Why an SNI call failed. Connection failures mean the item couldn't be
+// reached at all (session bus, address lookup, proxy setup); call failures
+// are errors the app itself returned, such as an unimplemented method.
+#[derive(Debug)]
+enum ActivationError {
+    Connection(zbus::Error),
+    Call { method: &'static str, error: zbus::Error },
+}
+
+impl std::fmt::Display for ActivationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActivationError::Connection(e) => write!(f, "couldn't reach the item: {e}"),
+            ActivationError::Call { method, error } => write!(f, "{method} failed: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ActivationError {}
+
+impl ActivationError {
+    fn method(&self) -> Option<&'static str> {
+        match self {
+            ActivationError::Connection(_) => None,
+            ActivationError::Call { method, .. } => Some(method),
+        }
+    }
+}
+
+async fn sni_proxy(bus_name: &str) -> zbus::Result<zbus::Proxy<'static>> {
+    let full_address = resolve_sni_address(bus_name).await?;
     let (dest, path) = parse_sni_address(&full_address);
 
-    let conn = Connection::session().await?;
-    let proxy: zbus::Proxy<'_> = zbus::proxy::Builder::new(&conn)
-        .destination(dest)?
-        .path(path.as_str())?
+    zbus::proxy::Builder::new(session_bus().await?)
+        .destination(dest.to_string())?
+        .path(path)?
         .interface("org.kde.StatusNotifierItem")?
         .build()
-        .await?;
+        .await
+}
 
-    proxy.call::<_, (i32, i32), ()>("Activate", &(x, y)).await?;
-    Ok(())
+// Calls a StatusNotifierItem method that takes the click position
+async fn sni_call(method: &'static str, bus_name: &str, x: i32, y: i32) -> Result<(), ActivationError> {
+    let proxy = sni_proxy(bus_name).await.map_err(ActivationError::Connection)?;
+    proxy
+        .call::<_, (i32, i32), ()>(method, &(x, y))
+        .await
+        .map_err(|error| ActivationError::Call { method, error })
 }
 
-async fn sni_context_menu(bus_name: &str, x: i32, y: i32) -> zbus::Result<()> {
-    let full_address = lookup_full_sni_address(bus_name).await?;
-    let (dest, path) = parse_sni_address(&full_address);
+async fn sni_activate(bus_name: &str, x: i32, y: i32) -> Result<(), ActivationError> {
+    provide_activation_token(bus_name).await;
+    sni_call("Activate", bus_name, x, y).await
+}
 
-    let conn = Connection::session().await?;
-    let proxy: zbus::Proxy<'_> = zbus::proxy::Builder::new(&conn)
-        .destination(dest)?
-        .path(path.as_str())?
-        .interface("org.kde.StatusNotifierItem")?
+// Hands the app an xdg-activation token so the compositor lets the window it
+// shows on Activate take focus. ProvideXdgActivationToken is a KDE extension;
+// apps without it still get the Activate, just without the token.
+async fn provide_activation_token(bus_name: &str) {
+    let token = tokio::time::timeout(ACTIVATION_TOKEN_TIMEOUT, activation::request_token()).await;
+    let Ok(Some(token)) = token else {
+        tracing::debug!("No xdg-activation token for Activate");
+        return;
+    };
+    let Ok(proxy) = sni_proxy(bus_name).await else {
+        return;
+    };
+    if let Err(e) = proxy.call::<_, (&str,), ()>("ProvideXdgActivationToken", &(token.as_str(),)).await {
+        tracing::debug!(address = bus_name, error = %e, "ProvideXdgActivationToken failed");
+    }
+}
+
+async fn sni_context_menu(bus_name: &str, x: i32, y: i32) -> Result<(), ActivationError> {
+    sni_call("ContextMenu", bus_name, x, y).await
+}
+
+async fn sni_secondary_activate(bus_name: &str, x: i32, y: i32) -> Result<(), ActivationError> {
+    sni_call("SecondaryActivate", bus_name, x, y).await
+}
+
+async fn sni_scroll(bus_name: &str, delta: i32, orientation: Orientation) -> Result<(), ActivationError> {
+    let proxy = sni_proxy(bus_name).await.map_err(ActivationError::Connection)?;
+    proxy
+        .call::<_, (i32, &str), ()>("Scroll", &(delta, orientation.as_str()))
+        .await
+        .map_err(|error| ActivationError::Call { method: "Scroll", error })
+}
+
+async fn dbusmenu_proxy(address: &str, menu_path: &str) -> zbus::Result<zbus::Proxy<'static>> {
+    let (dest, _) = parse_sni_address(address);
+
+    zbus::proxy::Builder::new(session_bus().await?)
+        .destination(dest.to_string())?
+        .path(menu_path.to_string())?
+        .interface("com.canonical.dbusmenu")?
         .build()
-        .await?;
+        .await
+}
 
-    proxy.call::<_, (i32, i32), ()>("ContextMenu", &(x, y)).await?;
-    Ok(())
+// Returns the app's needsUpdate flag
+async fn dbusmenu_about_to_show(address: &str, menu_path: &str, id: i32) -> zbus::Result<bool> {
+    let proxy = dbusmenu_proxy(address, menu_path).await?;
+    proxy.call("AboutToShow", &(id,)).await
 }
 
-async fn sni_secondary_activate(bus_name: &str, x: i32, y: i32) -> zbus::Result<()> {
-    let full_address = lookup_full_sni_address(bus_name).await?;
-    let (dest, path) = parse_sni_address(&full_address);
+// Full layout from the root, in the same shape system-tray hands us
+async fn dbusmenu_get_layout(address: &str, menu_path: &str) -> zbus::Result<TrayMenu> {
+    type Layout = (u32, (i32, HashMap<String, zbus::zvariant::OwnedValue>, Vec<zbus::zvariant::OwnedValue>));
 
-    let conn = Connection::session().await?;
-    let proxy: zbus::Proxy<'_> = zbus::proxy::Builder::new(&conn)
-        .destination(dest)?
-        .path(path.as_str())?
-        .interface("org.kde.StatusNotifierItem")?
+    let proxy = dbusmenu_proxy(address, menu_path).await?;
+    let (revision, (_, _, children)): Layout =
+        proxy.call("GetLayout", &(0i32, -1i32, Vec::<&str>::new())).await?;
+    let submenus = children
+        .iter()
+        .map(MenuItem::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(|e| zbus::Error::Failure(e.to_string()))?;
+    Ok(TrayMenu { id: revision, submenus })
+}
+
+async fn dbusmenu_event(address: &str, menu_path: &str, id: i32) -> zbus::Result<()> {
+    let proxy = dbusmenu_proxy(address, menu_path).await?;
+
+    // Event(id, eventId, data, timestamp) - data is unused for "clicked"
+    let data = zbus::zvariant::Value::from(0i32);
+    proxy
+        .call::<_, (i32, &str, zbus::zvariant::Value<'_>, u32), ()>("Event", &(id, "clicked", data, 0u32))
+        .await?;
+    Ok(())
+}
+
+// org.freedesktop.Notifications Notify with the server's default timeout
+async fn notify(summary: &str, body: &str) -> zbus::Result<()> {
+    let proxy: zbus::Proxy<'_> = zbus::proxy::Builder::new(session_bus().await?)
+        .destination("org.freedesktop.Notifications")?
+        .path("/org/freedesktop/Notifications")?
+        .interface("org.freedesktop.Notifications")?
         .build()
         .await?;
 
-    proxy.call::<_, (i32, i32), ()>("SecondaryActivate", &(x, y)).await?;
+    let actions: &[&str] = &[];
+    let hints: HashMap<&str, zbus::zvariant::Value<'_>> = HashMap::new();
+    proxy
+        .call::<_, _, u32>("Notify", &("vibebar", 0u32, "", summary, body, actions, hints, -1i32))
+        .await?;
     Ok(())
 }
 
-fn subscription(_state: &State) -> Subscription<Message> {
+// What this button does on the app with SNI id `id`: its per-app override,
+// then the `buttons` table, then the older per-button keys
+fn click_action(config: &Config, id: &str, click: ClickType) -> ClickAction {
+    let pick = |actions: &config::ButtonActions| match click {
+        ClickType::Left => actions.left,
+        ClickType::Right => actions.right,
+        ClickType::Middle => actions.middle,
+    };
+    if let Some(action) = config.overrides.get(id).and_then(pick).or_else(|| pick(&config.buttons)) {
+        return action;
+    }
+    match click {
+        ClickType::Left => match config.single_click_action {
+            SingleClickAction::Activate if config.left_click == LeftClickAction::Menu => ClickAction::ContextMenu,
+            SingleClickAction::Activate => ClickAction::Activate,
+            SingleClickAction::Menu => ClickAction::Menu,
+            SingleClickAction::None => ClickAction::None,
+        },
+        ClickType::Right => ClickAction::Menu,
+        ClickType::Middle => match config.middle_click_action {
+            MiddleClickAction::SecondaryActivate => ClickAction::SecondaryActivate,
+            MiddleClickAction::Quit => ClickAction::Quit,
+        },
+    }
+}
+
+// What a double left click sends. A left button set in [buttons] or the app's
+// override wins and double-clicks only if it activates; otherwise double
+// clicks activate (or ask for the app's menu with left_click = "menu")
+// whatever single_click_action says.
+fn double_click_call(config: &Config, id: &str) -> Option<SniCall> {
+    let left_set = config.overrides.get(id).is_some_and(|o| o.left.is_some()) || config.buttons.left.is_some();
+    if left_set {
+        return (click_action(config, id, ClickType::Left) == ClickAction::Activate).then_some(SniCall::Activate);
+    }
+    Some(match config.left_click {
+        LeftClickAction::Activate => SniCall::Activate,
+        LeftClickAction::Menu => SniCall::ContextMenu,
+    })
+}
+
+fn run_click_action(state: &mut State, address: String, action: ClickAction) -> iced::Task<Message> {
+    if action != ClickAction::None {
+        play_click_feedback(&state.config);
+    }
+    let call = match action {
+        ClickAction::Activate => SniCall::Activate,
+        ClickAction::ContextMenu => SniCall::ContextMenu,
+        ClickAction::SecondaryActivate => SniCall::SecondaryActivate,
+        ClickAction::Menu => {
+            // Give the app a chance to refresh dynamic entries before we render
+            let Some(menu_path) = state.tray_items.get(&address).and_then(|item| item.menu_path.clone()) else {
+                return open_menu_popup(state, address);
+            };
+            return iced::Task::perform(
+                async move {
+                    let menu = prepare_menu(&address, &menu_path).await;
+                    (address, menu)
+                },
+                |(address, menu)| Message::MenuPrepared(address, menu),
+            );
+        }
+        ClickAction::Quit => {
+            let target = state.tray_items.get(&address).and_then(|item| {
+                let id = find_quit_item(&item.menu.as_ref()?.submenus)?;
+                Some((item.menu_path.clone()?, id))
+            });
+            let Some((menu_path, id)) = target else {
+                tracing::info!(%address, "No quit entry in the menu, ignoring click");
+                return iced::Task::none();
+            };
+            return menu_event_task(address, menu_path, id);
+        }
+        ClickAction::None => return iced::Task::none(),
+    };
+    let (x, y) = state.mouse_position;
+    send_activation((address, call, x as i32, y as i32));
+    iced::Task::none()
+}
+
+// Dropped silently while disconnected; there is no item to act on anyway
+fn send_activation(request: ActivateRequest) {
+    if let Some(tx) = ACTIVATE_TX.lock().unwrap().as_ref() {
+        let _ = tx.send(request);
+    }
+}
+
+fn subscription(state: &State) -> Subscription<Message> {
+    let clock = if state.config.show_clock || state.bar_configs.values().any(|config| config.show_clock) {
+        iced::time::every(Duration::from_secs(1)).map(|_| Message::Tick)
+    } else {
+        Subscription::none()
+    };
+
+    // Frame ticks only while an icon fades in or a popup transitions; dormant otherwise
+    let animation = if state.tray_items.values().any(|item| item.opacity < 1.0) || popup_animating(state) {
+        iced::time::every(ANIMATION_FRAME).map(|_| Message::AnimationTick)
+    } else {
+        Subscription::none()
+    };
+
+    // With an explicit theme the desktop's preference doesn't matter
+    let color_scheme = if state.config.theme == ThemeMode::Auto {
+        Subscription::run(color_scheme_subscription)
+    } else {
+        Subscription::none()
+    };
+
+    // Menu navigation keys only matter while a context menu is open, and
+    // icon activation keys while an icon has focus
+    let menu_keys = if state.active_popup.is_some() {
+        iced::keyboard::listen().filter_map(menu_key_message)
+    } else if state.focused_icon.is_some() {
+        iced::keyboard::listen().filter_map(focus_key_message)
+    } else {
+        Subscription::none()
+    };
+
     Subscription::batch([
         Subscription::run(tray_subscription),
-        iced::event::listen_with(|event, _status, id| {
+        Subscription::run(signal_subscription),
+        Subscription::run(wayland::events).map(Message::Output),
+        clock,
+        animation,
+        color_scheme,
+        menu_keys,
+        iced::event::listen_with(|event, status, id| {
             match event {
+                // Recorded without a message; update reads it when it needs it
                 iced::Event::Mouse(iced::mouse::Event::CursorMoved { position }) => {
-                    Some(Message::MouseMoved(position))
+                    *LATEST_POINTER.lock().unwrap() = Some((id, position));
+                    None
+                }
+                // Presses that no widget handled (tray icons and menu entries capture theirs)
+                iced::Event::Mouse(iced::mouse::Event::ButtonPressed(_))
+                    if status == iced::event::Status::Ignored =>
+                {
+                    Some(Message::PointerPressed(id))
                 }
                 iced::Event::Window(iced::window::Event::Resized(size)) => {
                     Some(Message::WindowResized(id, size))
@@ -541,41 +3136,200 @@ fn subscription(_state: &State) -> Subscription<Message> {
                 iced::Event::Window(iced::window::Event::Opened { size, .. }) => {
                     Some(Message::WindowResized(id, size))
                 }
+                iced::Event::Window(iced::window::Event::Closed) => Some(Message::WindowClosed(id)),
+                iced::Event::Window(iced::window::Event::Rescaled(scale)) => {
+                    Some(Message::WindowRescaled(id, scale))
+                }
                 _ => None
             }
         }),
     ])
 }
 
+// SIGHUP reloads the config; SIGTERM and SIGINT quit
+fn signal_subscription() -> impl iced::futures::Stream<Item = Message> {
+    use tokio::signal::unix::{Signal, SignalKind, signal};
+
+    type Signals = (Signal, Signal, Signal);
+    iced::futures::stream::unfold(None::<Signals>, |signals| async move {
+        // Installed on first poll, inside the runtime
+        let (mut hangup, mut terminate, mut interrupt) = match signals {
+            Some(signals) => signals,
+            None => match (signal(SignalKind::hangup()), signal(SignalKind::terminate()), signal(SignalKind::interrupt())) {
+                (Ok(hangup), Ok(terminate), Ok(interrupt)) => (hangup, terminate, interrupt),
+                _ => {
+                    tracing::warn!("Failed to install signal handlers");
+                    return None;
+                }
+            },
+        };
+        let message = tokio::select! {
+            Some(()) = hangup.recv() => Message::ReloadConfig,
+            Some(()) = terminate.recv() => Message::Quit,
+            Some(()) = interrupt.recv() => Message::Quit,
+            else => return None,
+        };
+        Some((message, Some((hangup, terminate, interrupt))))
+    })
+}
+
+fn menu_key_message(event: iced::keyboard::Event) -> Option<Message> {
+    use iced::keyboard::{Event, Key, key::Named};
+
+    match event {
+        Event::KeyPressed { key: Key::Named(named), .. } => match named {
+            Named::ArrowUp => Some(Message::MenuHighlightMove(-1)),
+            Named::ArrowDown => Some(Message::MenuHighlightMove(1)),
+            Named::Enter => Some(Message::MenuActivateHighlighted),
+            Named::Escape => Some(Message::ClosePopup),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn focus_key_message(event: iced::keyboard::Event) -> Option<Message> {
+    use iced::keyboard::{Event, Key, key::Named};
+
+    match event {
+        Event::KeyPressed { key: Key::Named(named), .. } => match named {
+            Named::Enter | Named::Space => Some(Message::ActivateFocusedIcon),
+            Named::Escape => Some(Message::ClearIconFocus),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Launches the app whose .desktop file matches an SNI id, which is often the
+// desktop file's base name ("nm-applet") or its last reverse-DNS part
+// ("org.telegram.desktop" for "telegram")
+fn launch_desktop_entry(id: &str) {
+    let Some(path) = find_desktop_entry(id) else {
+        tracing::debug!(%id, "No desktop file to launch");
+        return;
+    };
+    tracing::info!(%id, path = %path.display(), "Activation failed, launching the app instead");
+    std::thread::spawn(move || {
+        if let Err(e) = std::process::Command::new("gio").arg("launch").arg(&path).status() {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to run gio launch");
+        }
+    });
+}
+
+fn find_desktop_entry(id: &str) -> Option<PathBuf> {
+    let id = id.to_lowercase();
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")));
+    let data_dirs = std::env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".to_string());
+    let dirs = data_home
+        .into_iter()
+        .chain(data_dirs.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .map(|dir| dir.join("applications"));
+
+    // Earlier dirs win, as in the XDG spec; within one, an exact name beats a suffix
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut suffix_match = None;
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let Some(stem) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(".desktop")) else {
+                continue;
+            };
+            let stem = stem.to_lowercase();
+            if stem == id {
+                return Some(path);
+            }
+            if suffix_match.is_none() && stem.ends_with(&format!(".{id}")) {
+                suffix_match = Some(path);
+            }
+        }
+        if suffix_match.is_some() {
+            return suffix_match;
+        }
+    }
+    None
+}
+
+// Client::new hosts a StatusNotifierWatcher itself when the session has
+// none. That keeps vibebar working on compositors without one, but apps
+// that started earlier found no watcher and may have given up on the tray,
+// which otherwise just looks like an empty bar.
+async fn connect_tray() -> system_tray::error::Result<Client> {
+    match watcher_running().await {
+        Ok(true) => {}
+        Ok(false) => tracing::warn!(
+            "No StatusNotifierWatcher on the session bus; hosting one. Your compositor or desktop \
+             may need an SNI watcher, and apps started before vibebar may need a restart to show up"
+        ),
+        Err(e) => tracing::debug!(error = %e, "Couldn't check for a StatusNotifierWatcher"),
+    }
+    Client::new().await
+}
+
+async fn watcher_running() -> zbus::Result<bool> {
+    let dbus = zbus::fdo::DBusProxy::new(session_bus().await?).await?;
+    let name = zbus::names::BusName::try_from("org.kde.StatusNotifierWatcher")?;
+    Ok(dbus.name_has_owner(name).await?)
+}
+
+// One connection attempt. On failure, waits out backoff and returns the
+// next one, so the caller retries instead of stalling for good.
+async fn connect_or_wait(
+    connect: impl Future<Output = system_tray::error::Result<Client>>,
+    backoff: Duration,
+) -> Result<Client, Duration> {
+    match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
+        Ok(Ok(client)) => return Ok(client),
+        Ok(Err(e)) => tracing::error!(error = %e, "Failed to connect to system tray"),
+        // A wedged session bus never answers
+        Err(_) => tracing::warn!(timeout = ?CONNECT_TIMEOUT, "Timed out connecting to system tray"),
+    }
+    tracing::debug!(?backoff, "Retrying tray connection");
+    tokio::time::sleep(backoff).await;
+    Err((backoff * 2).min(RECONNECT_BACKOFF_MAX))
+}
+
+// A fresh channel for clicks to reach the tray, on every connect. The old
+// receiver went away with the old client, so sends into it would be lost.
+fn register_activation() -> mpsc::UnboundedReceiver<ActivateRequest> {
+    let (activate_tx, activate_rx) = mpsc::unbounded_channel();
+    *ACTIVATE_TX.lock().unwrap() = Some(activate_tx);
+    activate_rx
+}
+
 fn tray_subscription() -> impl iced::futures::Stream<Item = Message> {
-    iced::futures::stream::unfold(TrayState::Disconnected, |state| async move {
+    let start = TrayState::Disconnected { backoff: RECONNECT_BACKOFF_MIN };
+    iced::futures::stream::unfold(start, |state| async move {
         match state {
-            TrayState::Disconnected => match Client::new().await {
+            TrayState::Disconnected { backoff } => match connect_or_wait(connect_tray(), backoff).await {
                 Ok(client) => {
                     let rx = client.subscribe();
 
-                    // Create activation channel and register sender
-                    let (activate_tx, activate_rx) = mpsc::unbounded_channel();
-                    let _ = ACTIVATE_TX.set(activate_tx);
-
-                    let initial: Vec<_> = {
-                        let items = client.items();
-                        let guard = items.lock().unwrap();
-                        guard
-                            .iter()
-                            .map(|(address, (item, _menu))| {
-                                let icon = IconData {
-                                    pixmap: item.icon_pixmap.clone(),
-                                    icon_name: item.icon_name.clone(),
-                                    icon_theme_path: item.icon_theme_path.clone(),
-                                };
-                                (address.clone(), icon)
-                            })
-                            .collect()
-                    };
+                    let activate_rx = register_activation();
+                    let ipc_tx = ACTIVATE_TX.lock().unwrap().clone();
+                    if let Some(Some(path)) = IPC_SOCKET.get()
+                        && let Some(tx) = ipc_tx
+                    {
+                        tokio::spawn(ipc::serve(path.clone(), client.items(), tx));
+                    }
+
+                    // Bus names may have been reused by other clients while we were away
+                    SNI_ADDRESSES.lock().unwrap().clear();
+
+                    let initial: Vec<_> = registered_items(&client)
+                        .into_iter()
+                        .map(|(address, item)| {
+                            prefetch_sni_address(address.clone());
+                            TrayEvent::Add { address, item }
+                        })
+                        .collect();
+                    tracing::info!(items = initial.len(), "Tray connected");
 
                     Some((
-                        Message::Tray(TrayEvent::Tick),
+                        Message::Tray(TrayEvent::Connected),
                         TrayState::SendingInitial {
                             client,
                             rx,
@@ -585,11 +3339,7 @@ fn tray_subscription() -> impl iced::futures::Stream<Item = Message> {
                         },
                     ))
                 }
-                Err(e) => {
-                    eprintln!("Failed to connect to system tray: {e}");
-                    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-                    Some((Message::Tray(TrayEvent::Tick), TrayState::Disconnected))
-                }
+                Err(backoff) => Some((Message::Tray(TrayEvent::Disconnected), TrayState::Disconnected { backoff })),
             },
             TrayState::SendingInitial {
                 client,
@@ -598,24 +3348,18 @@ fn tray_subscription() -> impl iced::futures::Stream<Item = Message> {
                 initial,
                 index,
             } => {
-                if index < initial.len() {
-                    let (address, icon) = initial[index].clone();
-                    Some((
-                        Message::Tray(TrayEvent::Add { address, icon }),
-                        TrayState::SendingInitial {
-                            client,
-                            rx,
-                            activate_rx,
-                            initial,
-                            index: index + 1,
-                        },
-                    ))
-                } else {
-                    Some((
-                        Message::Tray(TrayEvent::Tick),
-                        TrayState::Connected { client, rx, activate_rx },
-                    ))
-                }
+                let (events, rest) = initial_batch(&initial, index);
+                let next = match rest {
+                    Some(index) => TrayState::SendingInitial {
+                        client,
+                        rx,
+                        activate_rx,
+                        initial,
+                        index,
+                    },
+                    None => TrayState::Connected { client, rx, activate_rx },
+                };
+                Some((tray_batch_message(events), next))
             }
             TrayState::Connected { client, mut rx, mut activate_rx } => {
                 tokio::select! {
@@ -623,73 +3367,63 @@ fn tray_subscription() -> impl iced::futures::Stream<Item = Message> {
                     event_result = rx.recv() => {
                         match event_result {
                             Ok(event) => {
-                                let tray_event = match event {
-                                    Event::Add(address, item) => {
-                                        let icon = IconData {
-                                            pixmap: item.icon_pixmap.clone(),
-                                            icon_name: item.icon_name.clone(),
-                                            icon_theme_path: item.icon_theme_path.clone(),
-                                        };
-                                        TrayEvent::Add { address, icon }
-                                    }
-                                    Event::Update(address, update) => match update {
-                                        UpdateEvent::Icon {
-                                            icon_name,
-                                            icon_pixmap,
-                                        } => {
-                                            let icon = IconData {
-                                                pixmap: icon_pixmap,
-                                                icon_name,
-                                                icon_theme_path: None,
-                                            };
-                                            TrayEvent::Update { address, icon }
-                                        }
-                                        _ => {
-                                            return Some((
-                                                Message::Tray(TrayEvent::Tick),
-                                                TrayState::Connected { client, rx, activate_rx },
-                                            ));
-                                        }
-                                    },
-                                    Event::Remove(address) => TrayEvent::Remove { address },
-                                };
-                                Some((
-                                    Message::Tray(tray_event),
-                                    TrayState::Connected { client, rx, activate_rx },
-                                ))
+                                let (events, next) = coalesce_tray_events(event, &mut rx).await;
+                                let next = next.unwrap_or(TrayState::Connected { client, rx, activate_rx });
+                                Some((tray_batch_message(events), next))
                             }
                             Err(e) => {
-                                eprintln!("Tray subscription error: {e}");
-                                Some((Message::Tray(TrayEvent::Tick), TrayState::Disconnected))
+                                tracing::error!(error = %e, "Tray subscription error");
+                                // We were connected, so start over from the shortest backoff
+                                let next = TrayState::Disconnected { backoff: RECONNECT_BACKOFF_MIN };
+                                Some((Message::Tray(TrayEvent::Disconnected), next))
                             }
                         }
                     }
                     // Handle activation requests from UI
-                    Some((address, click_type, x, y)) = activate_rx.recv() => {
-                        match click_type {
-                            ClickType::Left => {
-                                // Check item_is_menu flag
-                                let item_is_menu = {
+                    Some((address, call, x, y)) = activate_rx.recv() => {
+                        let result = match call {
+                            SniCall::Activate => {
+                                let (item_is_menu, id) = {
                                     let items = client.items();
-                                    let guard = items.lock().unwrap();
-                                    guard.get(&address)
-                                        .map(|(item, _)| item.item_is_menu)
-                                        .unwrap_or(false)
+                                    let guard = lock_items(&items);
+                                    guard
+                                        .get(&address)
+                                        .map_or((false, None), |(item, _)| (item.item_is_menu, Some(item.id.clone())))
                                 };
-                                if item_is_menu {
-                                    let _ = sni_context_menu(&address, x, y).await;
+                                let result = if item_is_menu {
+                                    sni_context_menu(&address, x, y).await
                                 } else {
-                                    if sni_activate(&address, x, y).await.is_err() {
-                                        let _ = sni_context_menu(&address, x, y).await;
+                                    match sni_activate(&address, x, y).await {
+                                        // Unreachable items won't answer ContextMenu either
+                                        Err(e @ ActivationError::Connection(_)) => Err(e),
+                                        // Plenty of apps only implement the menu
+                                        Err(e) => {
+                                            tracing::debug!(%address, error = %e, "Activate failed, trying ContextMenu");
+                                            sni_context_menu(&address, x, y).await
+                                        }
+                                        Ok(()) => Ok(()),
                                     }
+                                };
+                                // Last resort for an item that answers nothing: start its app
+                                if result.is_err()
+                                    && LAUNCH_ON_ACTIVATE_FAILURE.load(Ordering::Relaxed)
+                                    && let Some(id) = id
+                                {
+                                    launch_desktop_entry(&id);
                                 }
+                                result
                             }
-                            ClickType::Right => {
-                                let _ = sni_context_menu(&address, x, y).await;
-                            }
-                            ClickType::Middle => {
-                                let _ = sni_secondary_activate(&address, x, y).await;
+                            SniCall::ContextMenu => sni_context_menu(&address, x, y).await,
+                            SniCall::SecondaryActivate => sni_secondary_activate(&address, x, y).await,
+                            SniCall::Scroll(delta, orientation) => sni_scroll(&address, delta, orientation).await,
+                        };
+                        match result {
+                            // Fires per wheel notch, and many apps ignore scrolling
+                            Err(e) if matches!(call, SniCall::Scroll(..)) => {
+                                tracing::debug!(%address, method = e.method(), error = %e, "SNI call failed");
                             }
+                            Err(e) => tracing::warn!(%address, method = e.method(), error = %e, "SNI call failed"),
+                            Ok(()) => {}
                         }
                         Some((
                             Message::Tray(TrayEvent::Tick),
@@ -702,35 +3436,383 @@ fn tray_subscription() -> impl iced::futures::Stream<Item = Message> {
     })
 }
 
+// The first event plus whatever follows within tray_batch, so a burst of
+// updates costs one pass through update and one redraw. A receive error ends
+// the batch with Disconnected and hands back the state to retry from.
+async fn coalesce_tray_events(
+    first: Event,
+    rx: &mut tokio::sync::broadcast::Receiver<Event>,
+) -> (Vec<TrayEvent>, Option<TrayState>) {
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(TRAY_BATCH_MS.load(Ordering::Relaxed));
+    let mut events: Vec<_> = tray_event(first).into_iter().collect();
+    // timeout_at polls before checking the deadline, so a zero window still
+    // takes everything already queued
+    while events.len() < TRAY_BATCH_MAX {
+        match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(Ok(event)) => events.extend(tray_event(event)),
+            Ok(Err(e)) => {
+                tracing::error!(error = %e, "Tray subscription error");
+                events.push(TrayEvent::Disconnected);
+                return (events, Some(TrayState::Disconnected { backoff: RECONNECT_BACKOFF_MIN }));
+            }
+            Err(_) => break,
+        }
+    }
+    if events.len() > 1 {
+        tracing::trace!(events = events.len(), "Coalesced tray events");
+    }
+    (events, None)
+}
+
+// The startup items from `index` on, up to TRAY_BATCH_MAX of them, like a
+// live burst. The last batch ends with InitialItemsSent; until then the index
+// to carry on from comes back too.
+fn initial_batch(initial: &[TrayEvent], index: usize) -> (Vec<TrayEvent>, Option<usize>) {
+    let end = (index + TRAY_BATCH_MAX).min(initial.len());
+    let mut events = initial[index..end].to_vec();
+    if end < initial.len() {
+        return (events, Some(end));
+    }
+    events.push(TrayEvent::InitialItemsSent);
+    (events, None)
+}
+
+// A single event goes out as is; only several make a TrayBatch
+fn tray_batch_message(events: Vec<TrayEvent>) -> Message {
+    match events.len() {
+        0 => Message::Tray(TrayEvent::Tick),
+        1 => Message::Tray(events.into_iter().next().unwrap()),
+        _ => Message::TrayBatch(events),
+    }
+}
+
+// None for updates the bar doesn't show
+fn tray_event(event: Event) -> Option<TrayEvent> {
+    let tray_event = match event {
+        Event::Add(address, item) => {
+            prefetch_sni_address(address.clone());
+            TrayEvent::Add {
+                address,
+                item: item_data(&item, None),
+            }
+        }
+        Event::Update(address, update) => match update {
+            UpdateEvent::Icon { icon_name, icon_pixmap } => {
+                let icon = IconData {
+                    pixmap: icon_pixmap,
+                    icon_name,
+                    icon_theme_path: None,
+                };
+                TrayEvent::Update { address, icon }
+            }
+            UpdateEvent::Menu(menu) => TrayEvent::Menu { address, menu },
+            UpdateEvent::Status(status) => TrayEvent::Status { address, status },
+            UpdateEvent::AttentionIcon(icon_name) => TrayEvent::AttentionIcon { address, icon_name },
+            UpdateEvent::OverlayIcon(icon_name) => TrayEvent::OverlayIcon { address, icon_name },
+            UpdateEvent::Title(title) => TrayEvent::Title { address, title },
+            UpdateEvent::Tooltip(tooltip) => TrayEvent::Tooltip {
+                address,
+                tooltip: tooltip.as_ref().and_then(tooltip_text),
+            },
+            _ => return None,
+        },
+        Event::Remove(address) => {
+            SNI_ADDRESSES.lock().unwrap().remove(&address);
+            TrayEvent::Remove { address }
+        }
+    };
+    Some(tray_event)
+}
+
 enum TrayState {
-    Disconnected,
+    Disconnected { backoff: Duration }, // Wait before the next attempt if this one fails
     SendingInitial {
         client: Client,
         rx: tokio::sync::broadcast::Receiver<Event>,
-        activate_rx: mpsc::UnboundedReceiver<(String, ClickType, i32, i32)>,
-        initial: Vec<(String, IconData)>,
+        activate_rx: mpsc::UnboundedReceiver<ActivateRequest>,
+        initial: Vec<TrayEvent>,
         index: usize,
     },
     Connected {
         client: Client,
         rx: tokio::sync::broadcast::Receiver<Event>,
-        activate_rx: mpsc::UnboundedReceiver<(String, ClickType, i32, i32)>,
+        activate_rx: mpsc::UnboundedReceiver<ActivateRequest>,
     },
 }
 
+// Exclusive zone and layer. A non-reserving bar floats over windows (even
+// fullscreen ones) instead of shrinking the usable area; popups parent to it
+// the same way.
+fn bar_layer(config: &Config) -> (i32, Layer) {
+    if config.reserve_space {
+        (config.bar_height as i32, Layer::Top)
+    } else {
+        (0, Layer::Overlay)
+    }
+}
+
+fn bar_anchor(edge: BarPosition) -> Anchor {
+    match edge {
+        BarPosition::Top => Anchor::Top | Anchor::Left | Anchor::Right,
+        BarPosition::Bottom => Anchor::Bottom | Anchor::Left | Anchor::Right,
+        BarPosition::Left => Anchor::Left | Anchor::Top | Anchor::Bottom,
+        BarPosition::Right => Anchor::Right | Anchor::Top | Anchor::Bottom,
+    }
+}
+
+// Layer surface size: full length along the edge (0), bar_height thick
+fn bar_size(config: &Config) -> (u32, u32) {
+    if config.is_vertical() {
+        (config.bar_height, 0)
+    } else {
+        (0, config.bar_height)
+    }
+}
+
+// Opens a bar on a newly seen output, unless outputs leaves it out or it
+// already has one
+fn open_bar(state: &mut State, output: wayland::Output) -> iced::Task<Message> {
+    if !state.config.outputs.is_empty() && !state.config.outputs.contains(&output.name) {
+        tracing::debug!(output = %output.name, "Output not in outputs, no bar");
+        return iced::Task::none();
+    }
+    if state.bar_outputs.values().any(|name| *name == output.name) {
+        return iced::Task::none();
+    }
+    let id = window::Id::unique();
+    let config = state.config.for_output(&output.name);
+    let (exclusive_zone, layer) = bar_layer(&config);
+    let open_task = iced::Task::done(Message::OpenBar {
+        id,
+        settings: NewLayerShellSettings {
+            size: Some(bar_size(&config)),
+            layer,
+            anchor: bar_anchor(config.edge()),
+            exclusive_zone: Some(exclusive_zone),
+            keyboard_interactivity: KeyboardInteractivity::OnDemand,
+            output_option: OutputOption::Output(output.wl_output),
+            namespace: Some(config.namespace.clone()),
+            ..Default::default()
+        },
+    });
+    tracing::info!(output = %output.name, "Opening bar");
+
+    // A bigger icon_size on this output needs the icons looked up again
+    let lookup_size = largest_icon_size(state);
+    state.bar_outputs.insert(id, output.name);
+    state.bar_configs.insert(id, config);
+    if largest_icon_size(state) > lookup_size {
+        set_icon_lookup_size(largest_icon_size(state), state.scale_factor);
+        return iced::Task::batch([open_task, resolve_all_icons(state)]);
+    }
+    open_task
+}
+
+#[derive(Parser)]
+#[command(version, about = "A Wayland status bar with a system tray")]
+struct Cli {
+    /// Config file to read instead of $XDG_CONFIG_HOME/vibebar/config.toml
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Output (wl_output name) to show a bar on; repeat for several, one bar each
+    #[arg(long = "output", value_name = "NAME")]
+    outputs: Vec<String>,
+
+    /// Screen edge: top, bottom, left or right
+    #[arg(long)]
+    position: Option<BarPosition>,
+
+    /// Bar orientation: horizontal or vertical
+    #[arg(long)]
+    orientation: Option<BarOrientation>,
+
+    /// Layer-shell namespace; give each instance its own to run several bars
+    #[arg(long)]
+    namespace: Option<String>,
+
+    /// Use the compact size preset (density = "compact")
+    #[arg(long)]
+    compact: bool,
+
+    /// Print the registered tray items and exit without opening a bar
+    #[arg(long, visible_alias = "dry-run")]
+    list_items: bool,
+
+    /// Print --list-items output as JSON
+    #[arg(long, requires = "list_items")]
+    json: bool,
+}
+
+// The config file with VIBEBAR_POSITION and command-line flags applied
+fn load_config(cli: &Cli) -> Config {
+    let mut config = config::load(cli.config.clone());
+    apply_args(&mut config, cli);
+    config.apply_density();
+    config
+}
+
+// Reads VIBEBAR_POSITION, then command-line flags (which take precedence)
+fn apply_args(config: &mut Config, cli: &Cli) {
+    if let Ok(value) = std::env::var("VIBEBAR_POSITION") {
+        match value.parse() {
+            Ok(position) => config.position = position,
+            Err(e) => tracing::warn!("VIBEBAR_POSITION: {e}"),
+        }
+    }
+
+    config.outputs.extend(cli.outputs.iter().cloned());
+    if let Some(position) = cli.position {
+        config.position = position;
+    }
+    if let Some(orientation) = cli.orientation {
+        config.orientation = orientation;
+    }
+    if let Some(namespace) = &cli.namespace {
+        config.namespace = namespace.clone();
+    }
+    if cli.compact {
+        config.density = Density::Compact;
+    }
+}
+
+// Items keep arriving after Client::new while their properties are fetched;
+// wait until the event stream goes quiet (or INITIAL_ITEMS_TIMEOUT passes)
+async fn collect_items() -> system_tray::error::Result<Vec<(String, Box<ItemData>)>> {
+    let client = connect_tray().await?;
+    let mut rx = client.subscribe();
+    let deadline = tokio::time::Instant::now() + INITIAL_ITEMS_TIMEOUT;
+    loop {
+        let wake = deadline.min(tokio::time::Instant::now() + LIST_ITEMS_SETTLE);
+        if !matches!(tokio::time::timeout_at(wake, rx.recv()).await, Ok(Ok(_))) {
+            break;
+        }
+    }
+
+    let mut items = registered_items(&client);
+    items.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(items)
+}
+
+// One item as --list-items reports it
+#[derive(serde::Serialize)]
+struct ItemListing<'a> {
+    address: &'a str,
+    id: &'a str,
+    title: Option<&'a str>,
+    status: String,
+    icon_name: Option<&'a str>,
+    has_pixmap: bool,
+    has_menu: bool,
+}
+
+fn print_items(items: &[(String, Box<ItemData>)], json: bool) {
+    let listings: Vec<ItemListing<'_>> = items
+        .iter()
+        .map(|(address, item)| ItemListing {
+            address,
+            id: &item.id,
+            title: item.title.as_deref(),
+            status: format!("{:?}", item.status),
+            icon_name: item.icon.icon_name.as_deref(),
+            has_pixmap: item.icon.pixmap.as_ref().is_some_and(|p| !p.is_empty()),
+            has_menu: item.menu_path.is_some(),
+        })
+        .collect();
+
+    if json {
+        match serde_json::to_string_pretty(&listings) {
+            Ok(out) => println!("{out}"),
+            Err(e) => tracing::error!(error = %e, "Failed to format items as JSON"),
+        }
+        return;
+    }
+
+    if listings.is_empty() {
+        println!("No tray items registered");
+    }
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    for listing in &listings {
+        println!("{}", listing.address);
+        println!("  id:        {}", listing.id);
+        println!("  title:     {}", listing.title.unwrap_or("-"));
+        println!("  status:    {}", listing.status);
+        println!("  icon_name: {}", listing.icon_name.unwrap_or("-"));
+        println!("  pixmap:    {}", yes_no(listing.has_pixmap));
+        println!("  menu:      {}", yes_no(listing.has_menu));
+    }
+}
+
 pub fn main() -> Result<(), iced_layershell::Error> {
-    daemon(init, namespace, update, view)
+    // Exits with usage on --help, --version or bad arguments
+    let cli = Cli::parse();
+
+    // RUST_LOG overrides; by default only our own info-level diagnostics show
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn,vibebar=info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut config = load_config(&cli);
+    if cli.list_items {
+        // No bar: just talk to the tray and report what's there
+        let runtime = tokio::runtime::Runtime::new().expect("failed to start tokio runtime");
+        match runtime.block_on(collect_items()) {
+            Ok(items) => print_items(&items, cli.json),
+            Err(e) => {
+                eprintln!("Failed to connect to system tray: {e}");
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    let _ = CLI.set(cli);
+    *ICON_THEME.lock().unwrap() = detect_icon_theme(config.icon_theme.as_deref());
+    let _ = IPC_SOCKET.set(ipc::socket_path(&config.namespace));
+
+    // Bars are opened per output as our connection reports them; without it
+    // layershell puts one on every output and outputs can't be honored
+    let connection = match wayland::connect() {
+        Some((connection, present)) => {
+            for output in config.outputs.iter().filter(|output| !present.contains(output)) {
+                tracing::warn!(%output, "Output not connected, its bar opens when it appears");
+            }
+            Some(connection)
+        }
+        None => {
+            tracing::warn!("Failed to track Wayland outputs, showing the bar on all outputs without their sections");
+            config.outputs.clear();
+            None
+        }
+    };
+    set_icon_lookup_size(config.icon_size, 1.0);
+    LAUNCH_ON_ACTIVATE_FAILURE.store(config.launch_on_activate_failure, Ordering::Relaxed);
+    TRAY_BATCH_MS.store(config.tray_batch.as_millis() as u64, Ordering::Relaxed);
+
+    let size = bar_size(&config);
+    let anchor = bar_anchor(config.edge());
+    let (exclusive_zone, layer) = bar_layer(&config);
+    let start_mode = if connection.is_some() { StartMode::Background } else { StartMode::AllScreens };
+
+    let namespace = config.namespace.clone();
+    daemon(move || init(config.clone()), move || namespace.clone(), update, view)
         .style(style)
         .theme(theme)
         .subscription(subscription)
         .settings(Settings {
             layer_settings: LayerShellSettings {
-                size: Some((0, 30)),
-                exclusive_zone: 30,
-                anchor: Anchor::Top | Anchor::Left | Anchor::Right,
-                start_mode: StartMode::Active,
+                size: Some(size),
+                exclusive_zone,
+                layer,
+                anchor,
+                start_mode,
+                // Lets the compositor focus the bar (and its menu popups) for keyboard navigation
+                keyboard_interactivity: KeyboardInteractivity::OnDemand,
                 ..Default::default()
             },
+            with_connection: connection,
             ..Default::default()
         })
         .run()
@@ -0,0 +1,880 @@
+// update-level checks. State and update don't need Wayland, so each test
+// builds a State with init and feeds it messages the way the daemon would.
+
+use std::task::{Context, Poll, Waker};
+
+use iced::futures::StreamExt;
+use iced_runtime::Action;
+use iced_runtime::core::widget::{Id, Operation, operation};
+use iced_runtime::user_interface::{self, UserInterface};
+
+use super::*;
+
+// Held by tests that look icons up or touch the icon cache, theme or lookup
+// size, which are process-wide. Lookups are awaited, hence a tokio mutex.
+static ICON_GLOBALS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+// A data dir with one icon theme, "VibebarTest", holding "vibebar-test-themed".
+// freedesktop_icons scans the search path once per process, so it goes on
+// XDG_DATA_DIRS before the first State (and so the first lookup) exists.
+static TEST_DATA_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    let dir = std::env::temp_dir().join(format!("vibebar-test-data-{}", std::process::id()));
+    let theme = dir.join("icons/VibebarTest");
+    std::fs::create_dir_all(theme.join("scalable/apps")).unwrap();
+    let index = "[Icon Theme]\nName=VibebarTest\nDirectories=scalable/apps\n\n\
+                 [scalable/apps]\nSize=48\nMinSize=1\nMaxSize=1024\nType=Scalable\n";
+    std::fs::write(theme.join("index.theme"), index).unwrap();
+    std::fs::write(theme.join("scalable/apps/vibebar-test-themed.svg"), "<svg/>").unwrap();
+
+    let system = std::env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".to_string());
+    // SAFETY: tests only read the environment through std, which serializes access
+    unsafe { std::env::set_var("XDG_DATA_DIRS", format!("{}:{system}", dir.display())) };
+    dir
+});
+
+// Held by tests that call register_activation to see what clicks send
+static ACTIVATE_CHANNEL: Mutex<()> = Mutex::new(());
+
+fn state() -> State {
+    state_with(Config::default())
+}
+
+fn state_with(config: Config) -> State {
+    LazyLock::force(&TEST_DATA_DIR);
+    init(config).0
+}
+
+fn item(id: &str) -> ItemData {
+    ItemData {
+        id: id.to_string(),
+        title: None,
+        status: Status::Active,
+        category: Category::ApplicationStatus,
+        icon: IconData::default(),
+        attention_icon: IconData::default(),
+        overlay_icon: IconData::default(),
+        tooltip: None,
+        menu_path: None, // Menu clicks open our popup right away instead of asking the app first
+        menu: None,
+    }
+}
+
+// Distinct SVG handles for telling icons apart; see svg_id
+fn svg_icon(name: &str) -> IconHandle {
+    IconHandle::Svg(svg::Handle::from_memory(format!("<svg><!-- {name} --></svg>").into_bytes()))
+}
+
+fn svg_id(handle: &IconHandle) -> Option<u64> {
+    match handle {
+        IconHandle::Svg(handle) => Some(handle.id()),
+        _ => None,
+    }
+}
+
+// update, and the messages it asks for straight away
+fn send(state: &mut State, message: Message) -> Vec<Message> {
+    messages(update(state, message))
+}
+
+fn add(state: &mut State, address: &str, item: ItemData) -> Vec<Message> {
+    send(state, Message::Tray(TrayEvent::Add { address: address.to_string(), item: Box::new(item) }))
+}
+
+// A bar as the daemon reports one: a window we didn't open, with a size
+fn bar(state: &mut State) -> window::Id {
+    let id = window::Id::unique();
+    send(state, Message::WindowResized(id, iced::Size::new(1920.0, 30.0)));
+    id
+}
+
+// The messages a task produces straight away. Work that would wait (timers,
+// D-Bus calls, the blocking pool) is dropped, so tests only see the
+// immediate follow-ups. iced yields once before each task starts, so a few
+// Pending polls in a row are expected before anything is ready.
+fn messages(task: iced::Task<Message>) -> Vec<Message> {
+    let Some(mut stream) = iced_runtime::task::into_stream(task) else {
+        return Vec::new();
+    };
+    let mut context = Context::from_waker(Waker::noop());
+    let mut messages = Vec::new();
+    let mut idle = 0;
+    while idle < 8 {
+        match stream.poll_next_unpin(&mut context) {
+            Poll::Ready(Some(Action::Output(message))) => {
+                messages.push(message);
+                idle = 0;
+            }
+            Poll::Ready(Some(_)) => idle = 0,
+            Poll::Ready(None) => break,
+            Poll::Pending => idle += 1,
+        }
+    }
+    messages
+}
+
+#[tokio::test]
+async fn right_click_opens_and_close_clears_the_popup() {
+    let mut state = state();
+    bar(&mut state);
+    add(&mut state, ":1.42/StatusNotifierItem", item("nm-applet"));
+    assert!(state.tray_items.contains_key(":1.42/StatusNotifierItem"));
+
+    let opened = send(&mut state, Message::TrayIconClicked(":1.42/StatusNotifierItem".to_string(), ClickType::Right));
+    let popup = opened.iter().find_map(|message| match message {
+        Message::OpenPopup { popup, .. } => Some(*popup),
+        _ => None,
+    });
+    assert!(popup.is_some());
+    assert_eq!(state.active_popup, popup);
+    assert_eq!(state.popup_for_address.as_deref(), Some(":1.42/StatusNotifierItem"));
+
+    send(&mut state, Message::ClosePopup);
+    assert_eq!(state.active_popup, None);
+    assert_eq!(state.popup_for_address, None);
+}
+
+
+#[tokio::test]
+async fn re_add_keeps_hover_scroll_and_the_icon_until_the_new_one_resolves() {
+    let mut state = state();
+    let address = ":1.7/StatusNotifierItem";
+    let mut data = item("spotify");
+    data.icon.icon_name = Some("spotify".to_string());
+    add(&mut state, address, data.clone());
+    send(&mut state, Message::IconResolved(address.to_string(), IconSlot::Main, data.icon.clone(), Some(svg_icon("a"))));
+    assert!(state.tray_items[address].icon.is_some());
+
+    // Unchanged icons aren't looked up again
+    assert!(state.tray_items.get_mut(address).unwrap().set_properties(data.clone()).is_empty());
+
+    // Hovered, with part of a scroll step built up
+    let hovered_at = Instant::now();
+    let exited_at = hovered_at + Duration::from_millis(5);
+    let tray_item = state.tray_items.get_mut(address).unwrap();
+    tray_item.hovered = true;
+    tray_item.hover_started = Some(hovered_at);
+    tray_item.hover_exit_at = Some(exited_at);
+    tray_item.scroll_accum = (0.25, -0.5);
+
+    // A changed one keeps showing the old handle rather than the placeholder
+    data.icon.icon_name = Some("spotify-playing".to_string());
+    data.title = Some("Spotify".to_string());
+    add(&mut state, address, data);
+    let tray_item = &state.tray_items[address];
+    assert!(tray_item.icon.is_some());
+    assert_eq!(tray_item.title.as_deref(), Some("Spotify"));
+    assert!(tray_item.hovered);
+    assert_eq!(tray_item.hover_started, Some(hovered_at));
+    assert_eq!(tray_item.hover_exit_at, Some(exited_at));
+    assert_eq!(tray_item.scroll_accum, (0.25, -0.5));
+}
+
+// What the daemon does with a message the tray subscription produces: one
+// update, then one view of the bar
+fn deliver(state: &mut State, bar: window::Id, message: Message) {
+    send(state, message);
+    let _ = view(state, bar);
+}
+
+#[tokio::test]
+async fn twenty_items_at_once_take_one_view() {
+    let mut state = state();
+    let bar = bar(&mut state);
+    let addresses: Vec<String> = (0..20).map(|n| format!(":1.{n}/StatusNotifierItem")).collect();
+
+    // At startup the items registered so far go out with InitialItemsSent in one batch
+    let initial: Vec<TrayEvent> = addresses
+        .iter()
+        .map(|address| TrayEvent::Add { address: address.clone(), item: Box::new(item("app")) })
+        .collect();
+    let (events, rest) = initial_batch(&initial, 0);
+    assert_eq!(rest, None);
+    let batch = tray_batch_message(events);
+    // All 20 adds, then InitialItemsSent
+    assert!(matches!(&batch, Message::TrayBatch(events) if events.len() == 21));
+    deliver(&mut state, bar, batch);
+    assert_eq!(state.tray_items.len(), 20);
+
+    // Later, 20 updates queued together coalesce the same way
+    let (tx, mut rx) = tokio::sync::broadcast::channel(64);
+    for address in &addresses {
+        tx.send(Event::Update(address.clone(), UpdateEvent::Title(Some("playing".to_string())))).unwrap();
+    }
+    let first = rx.recv().await.unwrap();
+    let (events, next) = coalesce_tray_events(first, &mut rx).await;
+    assert!(next.is_none());
+    let batch = tray_batch_message(events);
+    assert!(matches!(&batch, Message::TrayBatch(events) if events.len() == 20));
+    deliver(&mut state, bar, batch);
+    assert!(state.tray_items.values().all(|item| item.title.as_deref() == Some("playing")));
+}
+
+// A square pixmap with a full ARGB buffer
+fn pixmap(size: i32) -> IconPixmap {
+    IconPixmap { width: size, height: size, pixels: vec![255; (size * size * 4) as usize] }
+}
+
+#[test]
+fn truncated_pixmap_is_rejected() {
+    let mut truncated = pixmap(22);
+    truncated.pixels.pop();
+    assert!(pixmap_to_handle(&[truncated]).is_none());
+    assert!(pixmap_to_handle(&[pixmap(22)]).is_some());
+}
+
+#[test]
+fn parse_sni_address_cases() {
+    let cases = [
+        (":1.58", ":1.58", "/StatusNotifierItem"),
+        (":1.58/", ":1.58", "/StatusNotifierItem"),
+        (":1.58/StatusNotifierItem", ":1.58", "/StatusNotifierItem"),
+        (":1.58/a/b/c", ":1.58", "/a/b/c"),
+        (":1.58/org/blueman/sni/", ":1.58", "/org/blueman/sni"),
+        (":1.58//a//b", ":1.58", "/a/b"),
+        ("org.kde.StatusNotifierItem-1234-1", "org.kde.StatusNotifierItem-1234-1", "/StatusNotifierItem"),
+    ];
+    for (address, dest, path) in cases {
+        assert_eq!(parse_sni_address(address), (dest, path.to_string()), "{address}");
+    }
+}
+
+#[test]
+fn empty_and_oversized_pixmaps_are_skipped() {
+    let width = |pixmaps: &[IconPixmap]| match pixmap_to_handle(pixmaps)? {
+        image::Handle::Rgba { width, .. } => Some(width),
+        _ => None,
+    };
+    assert_eq!(width(&[pixmap(0)]), None);
+    assert_eq!(width(&[pixmap(2000)]), None);
+    // A usable pixmap next to them still gets picked
+    assert_eq!(width(&[pixmap(0), pixmap(2000), pixmap(22)]), Some(22));
+}
+
+#[tokio::test]
+async fn removing_the_item_closes_its_popup() {
+    let mut state = state();
+    bar(&mut state);
+    add(&mut state, ":1.42/StatusNotifierItem", item("nm-applet"));
+    send(&mut state, Message::TrayIconClicked(":1.42/StatusNotifierItem".to_string(), ClickType::Right));
+    assert!(state.active_popup.is_some());
+
+    let removed = send(&mut state, Message::Tray(TrayEvent::Remove { address: ":1.42/StatusNotifierItem".to_string() }));
+    assert!(removed.iter().any(|message| matches!(message, Message::ClosePopup)));
+}
+
+#[tokio::test]
+async fn identical_icon_update_is_not_looked_up_again() {
+    let mut state = state();
+    let address = ":1.9/StatusNotifierItem";
+    let mut data = item("vibebar-test");
+    data.icon.icon_name = Some("vibebar-test-icon".to_string());
+    add(&mut state, address, data.clone());
+
+    // The debounce window ends without a resolve task for the same icon...
+    send(&mut state, Message::Tray(TrayEvent::Update { address: address.to_string(), icon: data.icon.clone() }));
+    assert_eq!(update(&mut state, Message::IconDebounceElapsed(address.to_string())).units(), 0);
+    // ...but with one for a new icon
+    data.icon.icon_name = Some("vibebar-test-icon-2".to_string());
+    send(&mut state, Message::Tray(TrayEvent::Update { address: address.to_string(), icon: data.icon }));
+    assert_eq!(update(&mut state, Message::IconDebounceElapsed(address.to_string())).units(), 1);
+
+    // A name looked up before is answered from the cache, even once the file is gone
+    let _icons = ICON_GLOBALS.lock().await;
+    let dir = std::env::temp_dir().join(format!("vibebar-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("vibebar-test-cached.svg"), "<svg/>").unwrap();
+    let theme_path = dir.to_str();
+    assert!(lookup_icon("vibebar-test-cached", theme_path).is_some());
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(lookup_icon("vibebar-test-cached", theme_path).is_some());
+}
+
+#[tokio::test]
+async fn needs_attention_shows_the_attention_icon() {
+    let mut state = state();
+    let address = ":1.5/StatusNotifierItem";
+    let mut data = item("thunderbird");
+    data.icon.icon_name = Some("mail".to_string());
+    data.attention_icon.icon_name = Some("mail-unread".to_string());
+    add(&mut state, address, data.clone());
+    let (normal, attention) = (svg_icon("mail"), svg_icon("mail-unread"));
+    assert_ne!(svg_id(&normal), svg_id(&attention));
+    send(&mut state, Message::IconResolved(address.to_string(), IconSlot::Main, data.icon, Some(normal.clone())));
+    send(&mut state, Message::IconResolved(address.to_string(), IconSlot::Attention, data.attention_icon, Some(attention.clone())));
+    assert_eq!(svg_id(state.tray_items[address].displayed_icon()), svg_id(&normal));
+
+    send(&mut state, Message::Tray(TrayEvent::Status { address: address.to_string(), status: Status::NeedsAttention }));
+    assert_eq!(svg_id(state.tray_items[address].displayed_icon()), svg_id(&attention));
+}
+
+#[tokio::test]
+async fn clicks_reach_the_tray_after_a_reconnect() {
+    let mut state = state();
+    let address = ":1.12/StatusNotifierItem";
+    add(&mut state, address, item("discord"));
+
+    let _channel = ACTIVATE_CHANNEL.lock().unwrap();
+    let click = |state: &mut State| send(state, Message::TrayIconClicked(address.to_string(), ClickType::Left));
+    let activated = |rx: &mut mpsc::UnboundedReceiver<ActivateRequest>| {
+        std::iter::from_fn(|| rx.try_recv().ok()).any(|(to, call, _, _)| to == address && call == SniCall::Activate)
+    };
+
+    // What tray_subscription does on each connect
+    let mut old_rx = register_activation();
+    send(&mut state, Message::Tray(TrayEvent::Connected));
+    click(&mut state);
+    assert!(activated(&mut old_rx));
+
+    // The client goes away and comes back, well after that click
+    state.last_left_press = None;
+    send(&mut state, Message::Tray(TrayEvent::Disconnected));
+    let mut rx = register_activation();
+    send(&mut state, Message::Tray(TrayEvent::Connected));
+    click(&mut state);
+    assert!(activated(&mut rx));
+    assert!(!activated(&mut old_rx));
+}
+
+#[tokio::test]
+async fn configured_icon_theme_reaches_the_lookup() {
+    let _icons = ICON_GLOBALS.lock().await;
+    let mut state = state();
+    // Only the test theme has it; the default (hicolor) doesn't
+    assert!(lookup_icon("vibebar-test-themed", None).is_none());
+
+    let mut config = Config::default();
+    config.icon_theme = Some("VibebarTest".to_string());
+    let _ = apply_config(&mut state, config);
+    assert!(matches!(lookup_icon("vibebar-test-themed", None), Some(IconHandle::Svg(_))));
+
+    // Back to the default for the other tests
+    let _ = apply_config(&mut state, Config::default());
+    assert!(lookup_icon("vibebar-test-themed", None).is_none());
+}
+
+// The (x, width) of the popup a right click on address opens
+fn menu_popup(state: &mut State, address: &str) -> Option<(i32, u32)> {
+    let opened = send(state, Message::TrayIconClicked(address.to_string(), ClickType::Right));
+    opened.iter().find_map(|message| match message {
+        Message::OpenPopup { settings, .. } => Some((settings.position.0, settings.size.0)),
+        _ => None,
+    })
+}
+
+#[tokio::test]
+async fn menu_popup_is_sized_by_its_items_and_kept_on_the_output() {
+    let mut state = state();
+    let address = ":1.30/StatusNotifierItem";
+    let entry = |label: &str| MenuItem { label: Some(label.to_string()), visible: true, enabled: true, ..Default::default() };
+    let mut data = item("app");
+    data.menu = Some(TrayMenu { id: 0, submenus: vec![entry("Quit")] });
+    add(&mut state, address, data.clone());
+
+    // A short menu is narrower than the widest one allowed
+    let bar = bar(&mut state);
+    state.mouse_position = (1910.0, 15.0);
+    let (x, short) = menu_popup(&mut state, address).unwrap();
+    assert!(short < MENU_MAX_WIDTH as u32);
+    assert!(x + short as i32 <= 1920 - 4);
+
+    // A wide menu at the output's right edge ends 4px short of it
+    data.menu = Some(TrayMenu { id: 0, submenus: vec![entry("Quit"), entry(&"Preferences ".repeat(20))] });
+    add(&mut state, address, data);
+    let (x, wide) = menu_popup(&mut state, address).unwrap();
+    assert_eq!(wide, MENU_MAX_WIDTH as u32);
+    assert_eq!(x + wide as i32, 1920 - 4);
+
+    // On an output narrower than the menu, it shrinks to fit inside the margins
+    send(&mut state, Message::WindowResized(bar, iced::Size::new(200.0, 30.0)));
+    state.mouse_position = (100.0, 15.0);
+    assert_eq!(menu_popup(&mut state, address), Some((4, 192)));
+}
+
+// Where a laid-out bar puts its tray (the only scrollable) and its texts;
+// popups only have texts
+#[derive(Default)]
+struct Placement {
+    tray: Option<iced::Rectangle>,
+    texts: Vec<(String, iced::Rectangle)>,
+}
+
+impl Operation for Placement {
+    fn traverse(&mut self, operate: &mut dyn FnMut(&mut dyn Operation)) {
+        operate(self);
+    }
+
+    fn scrollable(
+        &mut self,
+        _: Option<&Id>,
+        bounds: iced::Rectangle,
+        _: iced::Rectangle,
+        _: iced::Vector,
+        _: &mut dyn operation::Scrollable,
+    ) {
+        self.tray = Some(bounds);
+    }
+
+    fn text(&mut self, _: Option<&Id>, bounds: iced::Rectangle, text: &str) {
+        self.texts.push((text.to_string(), bounds));
+    }
+}
+
+// The software renderer, enough to lay out and update a view without a window
+async fn renderer() -> iced::Renderer {
+    use iced_runtime::core::renderer::Headless;
+
+    iced::Renderer::new(iced::Font::default(), iced::Pixels(16.0), Some("tiny-skia")).await.unwrap()
+}
+
+// A window laid out at the given size, 1920x30 for the bar
+fn window_ui<'a>(
+    state: &'a State,
+    window: window::Id,
+    size: iced::Size,
+    renderer: &mut iced::Renderer,
+) -> UserInterface<'a, Message, Theme, iced::Renderer> {
+    UserInterface::build(view(state, window), size, user_interface::Cache::default(), renderer)
+}
+
+async fn layout(state: &State, window: window::Id, size: iced::Size) -> Placement {
+    let mut renderer = renderer().await;
+    let mut placement = Placement::default();
+    window_ui(state, window, size, &mut renderer).operate(&renderer, &mut placement);
+    placement
+}
+
+// The bar drawn by the software renderer, as RGBA rows of 1920 pixels
+async fn screenshot(state: &State, bar: window::Id) -> Vec<u8> {
+    use iced_runtime::core::renderer::{Headless, Style};
+
+    let mut renderer = renderer().await;
+    let mut ui = window_ui(state, bar, iced::Size::new(1920.0, 30.0), &mut renderer);
+    ui.draw(&mut renderer, &Theme::Dark, &Style::default(), iced::mouse::Cursor::Unavailable);
+    renderer.screenshot(iced::Size::new(1920, 30), 1.0, Color::TRANSPARENT)
+}
+
+// The messages a left click at point on the bar produces
+async fn click(state: &State, bar: window::Id, point: iced::Point) -> Vec<Message> {
+    let mut renderer = renderer().await;
+    let button = iced::mouse::Button::Left;
+    let events = [
+        iced::Event::Mouse(iced::mouse::Event::CursorMoved { position: point }),
+        iced::Event::Mouse(iced::mouse::Event::ButtonPressed(button)),
+        iced::Event::Mouse(iced::mouse::Event::ButtonReleased(button)),
+    ];
+    let mut messages = Vec::new();
+    window_ui(state, bar, iced::Size::new(1920.0, 30.0), &mut renderer).update(
+        &events,
+        iced::mouse::Cursor::Available(point),
+        &mut renderer,
+        &mut iced_runtime::core::clipboard::Null,
+        &mut messages,
+    );
+    messages
+}
+
+#[tokio::test]
+async fn tray_stays_at_the_right_end_of_the_bar() {
+    let mut state = state();
+    let bar = bar(&mut state);
+    send(&mut state, Message::Tray(TrayEvent::InitialItemsSent));
+    for n in 0..3 {
+        add(&mut state, &format!(":1.{n}/StatusNotifierItem"), item("app"));
+    }
+
+    let placement = layout(&state, bar, iced::Size::new(1920.0, 30.0)).await;
+    let tray = placement.tray.unwrap();
+    assert_eq!(tray.x + tray.width, 1920.0 - state.config.edge_margin);
+    // Nothing is in the left or center segments by default; the clock shares the right one
+    assert!(placement.texts.iter().all(|(_, bounds)| bounds.x > 1920.0 / 2.0 && bounds.x < tray.x));
+}
+
+#[tokio::test]
+async fn icons_keep_the_order_they_were_added_in() {
+    let mut state = state();
+    for id in ["c", "a", "b"] {
+        add(&mut state, &format!(":1.{id}/StatusNotifierItem"), item(id));
+    }
+    let order = |state: &State| ordered_items(state).iter().map(|(_, item)| item.id.clone()).collect::<Vec<_>>();
+    assert_eq!(order(&state), ["c", "a", "b"]);
+
+    // Updates, re-adds and other items coming and going don't reshuffle them
+    for _ in 0..10 {
+        let title = TrayEvent::Title { address: ":1.a/StatusNotifierItem".to_string(), title: Some("x".to_string()) };
+        send(&mut state, Message::Tray(title));
+        add(&mut state, ":1.b/StatusNotifierItem", item("b"));
+        add(&mut state, ":1.d/StatusNotifierItem", item("d"));
+        send(&mut state, Message::Tray(TrayEvent::Remove { address: ":1.d/StatusNotifierItem".to_string() }));
+        assert_eq!(order(&state), ["c", "a", "b"]);
+    }
+
+    // Alphabetical sorting is opt-in
+    state.config.sort = IconSort::Alphabetical;
+    assert_eq!(order(&state), ["a", "b", "c"]);
+}
+
+#[tokio::test]
+async fn a_burst_of_icon_updates_is_resolved_once() {
+    let mut state = state();
+    let address = ":1.21/StatusNotifierItem";
+    add(&mut state, address, item("nm-applet"));
+
+    // Only the first update of the burst starts a task: the debounce timer
+    let mut tasks = 0;
+    for n in 0..10 {
+        let icon = IconData { icon_name: Some(format!("network-wireless-{n}")), ..IconData::default() };
+        tasks += update(&mut state, Message::Tray(TrayEvent::Update { address: address.to_string(), icon })).units();
+    }
+    assert_eq!(tasks, 1);
+
+    // When it fires, the latest icon is resolved and nothing is left for another
+    assert_eq!(update(&mut state, Message::IconDebounceElapsed(address.to_string())).units(), 1);
+    assert_eq!(state.tray_items[address].icon_data.icon_name.as_deref(), Some("network-wireless-9"));
+    assert_eq!(update(&mut state, Message::IconDebounceElapsed(address.to_string())).units(), 0);
+}
+
+#[tokio::test]
+async fn a_bar_narrower_than_its_popup_is_still_the_bar() {
+    let mut state = state();
+    let address = ":1.33/StatusNotifierItem";
+    add(&mut state, address, item("app"));
+    // A vertical bar on a small output, say
+    let bar = window::Id::unique();
+    send(&mut state, Message::WindowResized(bar, iced::Size::new(40.0, 600.0)));
+
+    let opened = send(&mut state, Message::TrayIconClicked(address.to_string(), ClickType::Right));
+    let (parent, popup) = opened
+        .iter()
+        .find_map(|message| match message {
+            Message::OpenPopup { parent, popup, .. } => Some((*parent, *popup)),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(parent, bar);
+
+    // The popup's own, wider size doesn't make it a bar
+    send(&mut state, Message::WindowResized(popup, iced::Size::new(240.0, 120.0)));
+    assert_eq!(state.bars.keys().collect::<Vec<_>>(), [&bar]);
+}
+
+#[tokio::test]
+async fn an_item_without_an_icon_is_drawn_and_clickable() {
+    let mut state = state();
+    let bar = bar(&mut state);
+    send(&mut state, Message::Tray(TrayEvent::InitialItemsSent));
+    let address = ":1.40/StatusNotifierItem";
+    add(&mut state, address, item("broken-app"));
+    // Nothing to resolve: no name, no pixmap
+    assert!(state.tray_items[address].icon.is_none());
+    assert_eq!(svg_id(state.tray_items[address].displayed_icon()), svg_id(&FALLBACK_ICON));
+
+    let tray = layout(&state, bar, iced::Size::new(1920.0, 30.0)).await.tray.unwrap();
+    assert!(tray.width >= state.config.container_size);
+    let clicked = click(&state, bar, tray.center()).await;
+    assert!(clicked.iter().any(|message| matches!(message, Message::TrayIconClicked(to, ClickType::Left) if to == address)));
+}
+
+// The texts in the popup that message opens
+async fn popup_texts(state: &mut State, message: Message) -> Vec<String> {
+    let (popup, (width, height)) = send(state, message)
+        .into_iter()
+        .find_map(|message| match message {
+            Message::OpenPopup { popup, settings, .. } => Some((popup, settings.size)),
+            _ => None,
+        })
+        .unwrap();
+    let size = iced::Size::new(width as f32, height as f32);
+    layout(state, popup, size).await.texts.into_iter().map(|(text, _)| text).collect()
+}
+
+#[tokio::test]
+async fn a_tooltip_window_shows_the_tooltip_not_the_menu() {
+    let mut state = state();
+    bar(&mut state);
+    let address = ":1.44/StatusNotifierItem";
+    let mut data = item("spotify");
+    data.tooltip = Some(TooltipText { title: "Now playing".to_string(), body: String::new() });
+    let quit = MenuItem { label: Some("Quit".to_string()), visible: true, enabled: true, ..Default::default() };
+    data.menu = Some(TrayMenu { id: 0, submenus: vec![quit] });
+    add(&mut state, address, data);
+
+    // Hovered for longer than the tooltip delay
+    send(&mut state, Message::TrayIconHover(address.to_string(), true));
+    state.tray_items.get_mut(address).unwrap().hover_started = Some(Instant::now() - TOOLTIP_DELAY);
+    send(&mut state, Message::HoverDelayElapsed(address.to_string()));
+    let tooltip = popup_texts(&mut state, Message::TooltipDelayElapsed(address.to_string())).await;
+    assert_eq!(tooltip, ["Now playing"]);
+
+    // The menu that replaces it on a right click has its own content
+    let menu = popup_texts(&mut state, Message::TrayIconClicked(address.to_string(), ClickType::Right)).await;
+    assert!(menu.iter().any(|text| text == "Quit"));
+    assert!(!menu.iter().any(|text| text == "Now playing"));
+}
+
+#[tokio::test]
+async fn disabled_middle_click_sends_nothing() {
+    let mut state = state();
+    let address = ":1.50/StatusNotifierItem";
+    add(&mut state, address, item("pavucontrol"));
+    let _channel = ACTIVATE_CHANNEL.lock().unwrap();
+    let mut rx = register_activation();
+
+    state.config.enable_middle_click = false;
+    send(&mut state, Message::TrayIconClicked(address.to_string(), ClickType::Middle));
+    assert!(rx.try_recv().is_err());
+
+    state.config.enable_middle_click = true;
+    send(&mut state, Message::TrayIconClicked(address.to_string(), ClickType::Middle));
+    assert!(matches!(rx.try_recv(), Ok((to, SniCall::SecondaryActivate, _, _)) if to == address));
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_connect_that_never_answers_is_retried() {
+    let started = tokio::time::Instant::now();
+    let next = connect_or_wait(std::future::pending(), RECONNECT_BACKOFF_MIN).await.err();
+    // Given up on after the timeout, then the backoff, and the next attempt waits longer
+    assert_eq!(started.elapsed(), CONNECT_TIMEOUT + RECONNECT_BACKOFF_MIN);
+    assert_eq!(next, Some(RECONNECT_BACKOFF_MIN * 2));
+}
+
+// Every message a task produces, waiting for timers and the blocking pool
+async fn finish(task: iced::Task<Message>) -> Vec<Message> {
+    let Some(stream) = iced_runtime::task::into_stream(task) else {
+        return Vec::new();
+    };
+    stream
+        .filter_map(|action| async move {
+            match action {
+                Action::Output(message) => Some(message),
+                _ => None,
+            }
+        })
+        .collect()
+        .await
+}
+
+#[tokio::test]
+async fn icons_are_looked_up_after_update_returns() {
+    let _icons = ICON_GLOBALS.lock().await;
+    let mut state = state();
+    let dir = std::env::temp_dir().join(format!("vibebar-test-async-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("vibebar-test-async.svg"), "<svg/>").unwrap();
+    let address = ":1.60/StatusNotifierItem";
+    let mut data = item("app");
+    data.icon.icon_name = Some("vibebar-test-async".to_string());
+    data.icon.icon_theme_path = dir.to_str().map(str::to_string);
+
+    // The item is there straight away, drawn with the placeholder
+    let added = TrayEvent::Add { address: address.to_string(), item: Box::new(data) };
+    let task = update(&mut state, Message::Tray(added));
+    assert!(state.tray_items[address].icon.is_none());
+    assert_eq!(task.units(), 1);
+
+    // The lookup runs on the blocking pool and comes back as a message
+    let resolved = finish(task).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    for message in resolved {
+        send(&mut state, message);
+    }
+    assert!(state.tray_items[address].icon.is_some());
+}
+
+#[tokio::test]
+async fn xpm_and_ico_icons_are_loaded() {
+    let _icons = ICON_GLOBALS.lock().await;
+    let mut state = state();
+    let dir = std::env::temp_dir().join(format!("vibebar-test-formats-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let xpm = r#"/* XPM */
+static char *icon[] = {
+"3 2 2 1",
+"  c None",
+". c #FF0000",
+". .",
+" . "
+};
+"#;
+    std::fs::write(dir.join("vibebar-test-old.xpm"), xpm).unwrap();
+    // A color count no 16x16 image could use, which must not be allocated for
+    let bogus = "/* XPM */\nstatic char *icon[] = {\n\"16 16 99999999999999 1\",\n};\n";
+    assert_eq!(xpm::decode(bogus), None);
+    std::fs::write(dir.join("vibebar-test-bogus.xpm"), bogus).unwrap();
+    image_crate::RgbaImage::from_pixel(16, 16, image_crate::Rgba([0, 0, 255, 255]))
+        .save(dir.join("vibebar-test-ported.ico"))
+        .unwrap();
+
+    for (n, name) in ["vibebar-test-old", "vibebar-test-ported", "vibebar-test-bogus"].into_iter().enumerate() {
+        let mut data = item(name);
+        data.icon.icon_name = Some(name.to_string());
+        data.icon.icon_theme_path = dir.to_str().map(str::to_string);
+        let added = TrayEvent::Add { address: format!(":1.7{n}/StatusNotifierItem"), item: Box::new(data) };
+        for message in finish(update(&mut state, Message::Tray(added))).await {
+            send(&mut state, message);
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let size = |address: &str| match state.tray_items[address].icon.as_ref()? {
+        IconHandle::Raster(image::Handle::Rgba { width, height, .. }) => Some((*width, *height)),
+        _ => None,
+    };
+    assert_eq!(size(":1.70/StatusNotifierItem"), Some((3, 2)));
+    assert_eq!(size(":1.71/StatusNotifierItem"), Some((16, 16)));
+    assert!(state.tray_items[":1.72/StatusNotifierItem"].icon.is_none());
+}
+
+#[tokio::test]
+async fn a_stray_press_right_after_opening_keeps_the_popup() {
+    let mut state = state();
+    let bar = bar(&mut state);
+    let address = ":1.80/StatusNotifierItem";
+    add(&mut state, address, item("app"));
+    send(&mut state, Message::TrayIconClicked(address.to_string(), ClickType::Right));
+
+    // The press that opened it, reported on the bar after the fact
+    let stray = send(&mut state, Message::PointerPressed(bar));
+    assert!(!stray.iter().any(|message| matches!(message, Message::ClosePopup)));
+
+    // Once the grace period is over, a press on the bar is outside the popup
+    state.popup_opened_at = Some(Instant::now() - POPUP_OPEN_GRACE);
+    let outside = send(&mut state, Message::PointerPressed(bar));
+    assert!(outside.iter().any(|message| matches!(message, Message::ClosePopup)));
+}
+
+#[tokio::test]
+async fn a_poisoned_item_map_is_still_read() {
+    let address = ":1.90/StatusNotifierItem";
+    let sni = StatusNotifierItem {
+        id: "keepassxc".to_string(),
+        category: Category::ApplicationStatus,
+        title: Some("KeePassXC".to_string()),
+        status: Status::Active,
+        window_id: 0,
+        icon_theme_path: None,
+        icon_name: None,
+        icon_pixmap: None,
+        overlay_icon_name: None,
+        overlay_icon_pixmap: None,
+        attention_icon_name: None,
+        attention_icon_pixmap: None,
+        attention_movie_name: None,
+        tool_tip: None,
+        item_is_menu: true,
+        menu: None,
+    };
+    let items = std::sync::Arc::new(Mutex::new(BaseMap::from([(address.to_string(), (sni, None))])));
+
+    // A panic inside system_tray while it holds the lock
+    let held = items.clone();
+    let panicked = std::thread::spawn(move || {
+        let _guard = held.lock().unwrap();
+        panic!("system_tray panicked");
+    })
+    .join();
+    assert!(panicked.is_err() && items.is_poisoned());
+
+    let (item, menu) = lock_items(&items)[address].clone();
+    assert!(item.item_is_menu);
+    let mut state = state();
+    add(&mut state, address, *item_data(&item, menu));
+    assert_eq!(state.tray_items[address].title.as_deref(), Some("KeePassXC"));
+}
+
+#[tokio::test]
+async fn a_resolution_change_moves_the_popup_clamp() {
+    let mut state = state();
+    let address = ":1.95/StatusNotifierItem";
+    add(&mut state, address, item("app"));
+    let bar = bar(&mut state);
+    state.mouse_position = (1900.0, 15.0);
+    let (x, width) = menu_popup(&mut state, address).unwrap();
+    assert_eq!(x + width as i32, 1920 - 4);
+    let old = state.active_popup.unwrap();
+
+    // The output drops to 1280 wide: the open menu is reopened inside the new edge
+    let resized = send(&mut state, Message::WindowResized(bar, iced::Size::new(1280.0, 30.0)));
+    assert!(resized.iter().any(|message| matches!(message, Message::CloseWindow(id) if *id == old)));
+    let reopened = resized.iter().find_map(|message| match message {
+        Message::OpenPopup { settings, .. } => Some((settings.position.0, settings.size.0)),
+        _ => None,
+    });
+    assert_eq!(reopened, Some((1280 - 4 - width as i32, width)));
+    assert_eq!(state.popup_for_address.as_deref(), Some(address));
+}
+
+#[tokio::test]
+async fn symbolic_icons_are_drawn_in_the_configured_color() {
+    let _icons = ICON_GLOBALS.lock().await;
+    let mut config = Config::default();
+    config.colors.symbolic_color = Some(Color::from_rgb8(255, 0, 0));
+    config.reduce_motion = true; // Drawn at full opacity straight away
+    let mut state = state_with(config);
+    let bar = bar(&mut state);
+    send(&mut state, Message::Tray(TrayEvent::InitialItemsSent));
+
+    // Black art; only the name says it is symbolic
+    let dir = std::env::temp_dir().join(format!("vibebar-test-symbolic-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let art = r#"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16"><rect width="16" height="16"/></svg>"#;
+    std::fs::write(dir.join("vibebar-test-foo-symbolic.svg"), art).unwrap();
+    std::fs::write(dir.join("vibebar-test-foo.svg"), art).unwrap();
+    for name in ["vibebar-test-foo-symbolic", "vibebar-test-foo"] {
+        let mut data = item(name);
+        data.icon.icon_name = Some(name.to_string());
+        data.icon.icon_theme_path = dir.to_str().map(str::to_string);
+        let added = TrayEvent::Add { address: format!(":1.{name}/StatusNotifierItem"), item: Box::new(data) };
+        for message in finish(update(&mut state, Message::Tray(added))).await {
+            send(&mut state, message);
+        }
+    }
+    let symbolic = ":1.vibebar-test-foo-symbolic/StatusNotifierItem";
+    let plain = ":1.vibebar-test-foo/StatusNotifierItem";
+    assert!(matches!(state.tray_items[symbolic].icon, Some(IconHandle::Symbolic(_))));
+    assert!(matches!(state.tray_items[plain].icon, Some(IconHandle::Svg(_))));
+
+    // Only the plain icon stays black; the symbolic one is filled red
+    let tray = layout(&state, bar, iced::Size::new(1920.0, 30.0)).await.tray.unwrap();
+    let pixels = screenshot(&state, bar).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    let pixel = |x: f32| {
+        let at = ((tray.center_y() as usize) * 1920 + x as usize) * 4;
+        [pixels[at], pixels[at + 1], pixels[at + 2]]
+    };
+    let icon = state.config.container_size;
+    let spacing = state.config.tray_spacing;
+    assert_eq!(pixel(tray.x + icon / 2.0), [255, 0, 0]);
+    assert_eq!(pixel(tray.x + icon + spacing + icon / 2.0), [0, 0, 0]);
+}
+
+#[test]
+fn theme_path_layouts_are_searched() {
+    let _icons = ICON_GLOBALS.blocking_lock();
+    let target = ICON_LOOKUP_SIZE.load(Ordering::Relaxed);
+    let root = std::env::temp_dir().join(format!("vibebar-test-layouts-{}", std::process::id()));
+    let find = |files: &[String]| {
+        let _ = std::fs::remove_dir_all(&root);
+        for file in files {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let found = find_in_theme_path(&root, "app");
+        found.map(|path| path.strip_prefix(&root).unwrap().to_str().unwrap().to_string())
+    };
+    let found = |files: &[&str]| find(&files.iter().map(|file| file.to_string()).collect::<Vec<_>>());
+
+    // Flat, hicolor-style, and sizes without the theme prefix
+    assert_eq!(found(&["app.png", "other.png"]).as_deref(), Some("app.png"));
+    assert_eq!(found(&["hicolor/48x48/apps/app.png"]).as_deref(), Some("hicolor/48x48/apps/app.png"));
+    assert_eq!(found(&["32/apps/app.png"]).as_deref(), Some("32/apps/app.png"));
+    // Scalable art wins over any raster
+    assert_eq!(found(&["hicolor/256x256/apps/app.png", "scalable/app.svg"]).as_deref(), Some("scalable/app.svg"));
+    // The smallest raster at least the lookup size, not the largest
+    let sizes = [target / 2, target, target * 2].map(|size| format!("hicolor/{size}x{size}/apps/app.png"));
+    assert_eq!(find(&sizes), Some(sizes[1].clone()));
+    // All below the lookup size: the largest of them
+    let small = [target / 4, target / 2].map(|size| format!("{size}/apps/app.png"));
+    assert_eq!(find(&small), Some(small[1].clone()));
+    let _ = std::fs::remove_dir_all(&root);
+}
@@ -0,0 +1,190 @@
+// Our own Wayland connection, handed to iced_layershell so the wl_output
+// objects we bind here can place bars. layershellev only targets outputs by
+// object, not by name, so outputs are tracked here by name (wl_output v4, or
+// xdg-output on older compositors) and reported as they come and go. The
+// same connection serves activation tokens (see activation.rs).
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tokio::sync::mpsc;
+use wayland_client::globals::{GlobalListContents, registry_queue_init};
+use wayland_client::protocol::wl_output::{self, WlOutput};
+use wayland_client::protocol::wl_registry;
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols::xdg::xdg_output::zv1::client::zxdg_output_manager_v1::{self, ZxdgOutputManagerV1};
+use wayland_protocols::xdg::xdg_output::zv1::client::zxdg_output_v1::{self, ZxdgOutputV1};
+
+// Taken by the first events() stream; connect() fills it
+static EVENTS: Mutex<Option<mpsc::UnboundedReceiver<OutputEvent>>> = Mutex::new(None);
+
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub name: String, // "DP-1", "HDMI-A-1", ...
+    pub wl_output: WlOutput,
+}
+
+#[derive(Debug, Clone)]
+pub enum OutputEvent {
+    Added(Output),
+    Removed(String), // Name of an output that went away
+}
+
+struct Tracked {
+    wl_output: WlOutput,
+    xdg_output: Option<ZxdgOutputV1>, // Where the name comes from before wl_output v4
+    name: Option<String>,             // Until the compositor tells us
+}
+
+pub struct WaylandState {
+    xdg_output_manager: Option<ZxdgOutputManagerV1>,
+    outputs: HashMap<u32, Tracked>, // By registry global name
+    tx: mpsc::UnboundedSender<OutputEvent>,
+}
+
+// Connects, learns the current outputs and keeps watching for hotplug on a
+// thread of its own. Returns the connection for iced_layershell and the
+// names of the outputs present now; None without a Wayland compositor.
+pub fn connect() -> Option<(Connection, Vec<String>)> {
+    let connection = Connection::connect_to_env().ok()?;
+    let (globals, mut queue) = registry_queue_init::<WaylandState>(&connection).ok()?;
+    let handle = queue.handle();
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut state = WaylandState {
+        xdg_output_manager: globals.bind(&handle, 2..=3, ()).ok(),
+        outputs: HashMap::new(),
+        tx,
+    };
+    globals.contents().with_list(|list| {
+        for global in list.iter().filter(|global| global.interface == WlOutput::interface().name) {
+            state.bind_output(globals.registry(), global.name, global.version, &handle);
+        }
+    });
+    crate::activation::bind(&globals, &handle, &connection);
+    // One roundtrip for the binds, one for the names and the seat's pointer
+    queue.roundtrip(&mut state).ok()?;
+    queue.roundtrip(&mut state).ok()?;
+
+    let names = state.outputs.values().filter_map(|tracked| tracked.name.clone()).collect();
+    *EVENTS.lock().unwrap() = Some(rx);
+    std::thread::spawn(move || {
+        while queue.blocking_dispatch(&mut state).is_ok() {}
+        tracing::warn!("Lost the Wayland connection, no longer tracking outputs");
+    });
+    Some((connection, names))
+}
+
+// Every output already present, then each one added or removed afterwards
+pub fn events() -> impl iced::futures::Stream<Item = OutputEvent> {
+    iced::futures::stream::unfold(None, |rx: Option<mpsc::UnboundedReceiver<OutputEvent>>| async move {
+        let mut rx = match rx {
+            Some(rx) => rx,
+            None => EVENTS.lock().unwrap().take()?,
+        };
+        let event = rx.recv().await?;
+        Some((event, Some(rx)))
+    })
+}
+
+impl WaylandState {
+    fn bind_output(&mut self, registry: &wl_registry::WlRegistry, global: u32, version: u32, handle: &QueueHandle<Self>) {
+        let wl_output: WlOutput = registry.bind(global, version.min(4), handle, global);
+        // Before v4 wl_output has no name event; xdg-output carries it instead
+        let xdg_output = if version < 4 {
+            let xdg_output = self.xdg_output_manager.as_ref().map(|manager| manager.get_xdg_output(&wl_output, handle, global));
+            if xdg_output.is_none() {
+                tracing::warn!(global, "Output has no name (compositor lacks wl_output v4 and xdg-output)");
+            }
+            xdg_output
+        } else {
+            None
+        };
+        self.outputs.insert(global, Tracked { wl_output, xdg_output, name: None });
+    }
+
+    fn name_output(&mut self, global: u32, name: String) {
+        let Some(tracked) = self.outputs.get_mut(&global) else {
+            return;
+        };
+        // Names don't change; announce each output once
+        if tracked.name.is_some() {
+            return;
+        }
+        tracked.name = Some(name.clone());
+        let _ = self.tx.send(OutputEvent::Added(Output { name, wl_output: tracked.wl_output.clone() }));
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for WaylandState {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        handle: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global { name, interface, version } if interface == WlOutput::interface().name => {
+                state.bind_output(registry, name, version, handle);
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                if let Some(tracked) = state.outputs.remove(&name) {
+                    if let Some(xdg_output) = tracked.xdg_output {
+                        xdg_output.destroy();
+                    }
+                    if tracked.wl_output.version() >= 3 {
+                        tracked.wl_output.release();
+                    }
+                    if let Some(name) = tracked.name {
+                        let _ = state.tx.send(OutputEvent::Removed(name));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlOutput, u32> for WaylandState {
+    fn event(
+        state: &mut Self,
+        _: &WlOutput,
+        event: wl_output::Event,
+        global: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_output::Event::Name { name } = event {
+            state.name_output(*global, name);
+        }
+    }
+}
+
+// The manager sends no events
+impl Dispatch<ZxdgOutputManagerV1, ()> for WaylandState {
+    fn event(
+        _: &mut Self,
+        _: &ZxdgOutputManagerV1,
+        _: zxdg_output_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, u32> for WaylandState {
+    fn event(
+        state: &mut Self,
+        _: &ZxdgOutputV1,
+        event: zxdg_output_v1::Event,
+        global: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zxdg_output_v1::Event::Name { name } = event {
+            state.name_output(*global, name);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Minimal XPM (X PixMap) decoder for the old tray icons some apps still ship.
+// The image crate has no XPM support. Handles XPM3 with any number of
+// characters per pixel; colors may be hex, "None" or a few basic names, and
+// other names decode as mid gray.
+
+use std::collections::HashMap;
+
+const MAX_SIZE: usize = 1024; // Larger images are treated as bogus
+
+// Decodes to (width, height, RGBA pixels)
+pub fn decode(source: &str) -> Option<(u32, u32, Vec<u8>)> {
+    // Every piece of data is a C string literal; comments carry no quotes
+    let mut strings = source.split('"').skip(1).step_by(2);
+
+    let header = strings.next()?;
+    let mut values = header.split_whitespace().map(|v| v.parse::<usize>().ok());
+    let (width, height, color_count, cpp) = (values.next()??, values.next()??, values.next()??, values.next()??);
+    if width == 0 || height == 0 || cpp == 0 || width.max(height) > MAX_SIZE {
+        return None;
+    }
+    // Every color beyond one per pixel would go unused; more is a corrupt header
+    if color_count > width * height {
+        return None;
+    }
+
+    let mut colors = HashMap::with_capacity(color_count);
+    for _ in 0..color_count {
+        let line = strings.next()?;
+        colors.insert(line.get(..cpp)?, parse_color_spec(line.get(cpp..)?)?);
+    }
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for _ in 0..height {
+        let row = strings.next()?;
+        for x in 0..width {
+            let key = row.get(x * cpp..(x + 1) * cpp)?;
+            rgba.extend_from_slice(colors.get(key)?);
+        }
+    }
+    Some((width as u32, height as u32, rgba))
+}
+
+// A color line after its pixel key, e.g. "c #ff0000 m black". The color
+// visual ("c") wins, then grayscale, then mono.
+fn parse_color_spec(spec: &str) -> Option<[u8; 4]> {
+    let mut visuals: Vec<(&str, String)> = Vec::new();
+    for token in spec.split_whitespace() {
+        if matches!(token, "c" | "m" | "g" | "g4" | "s") {
+            visuals.push((token, String::new()));
+        } else if let Some((_, value)) = visuals.last_mut() {
+            // Names such as "light gray" span several tokens
+            if !value.is_empty() {
+                value.push(' ');
+            }
+            value.push_str(token);
+        }
+    }
+
+    let value = ["c", "g", "g4", "m"]
+        .iter()
+        .find_map(|key| visuals.iter().find(|(k, _)| k == key))
+        .map(|(_, value)| value)?;
+    Some(color_value(value))
+}
+
+fn color_value(value: &str) -> [u8; 4] {
+    if let Some(hex) = value.strip_prefix('#')
+        && let Some(rgb) = parse_hex(hex)
+    {
+        return rgb;
+    }
+    match value.to_ascii_lowercase().as_str() {
+        "none" => [0, 0, 0, 0],
+        "black" => [0, 0, 0, 255],
+        "white" => [255, 255, 255, 255],
+        "red" => [255, 0, 0, 255],
+        "green" => [0, 255, 0, 255],
+        "blue" => [0, 0, 255, 255],
+        "yellow" => [255, 255, 0, 255],
+        "cyan" => [0, 255, 255, 255],
+        "magenta" => [255, 0, 255, 255],
+        _ => [128, 128, 128, 255],
+    }
+}
+
+// "#rgb", "#rrggbb" or "#rrrrggggbbbb"; only the top 8 bits of each channel are kept
+fn parse_hex(hex: &str) -> Option<[u8; 4]> {
+    if !matches!(hex.len(), 3 | 6 | 12) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits = hex.len() / 3;
+    let channel = |i: usize| {
+        let part = &hex[i * digits..(i + 1) * digits];
+        match digits {
+            1 => u8::from_str_radix(part, 16).map(|v| v * 17),
+            _ => u8::from_str_radix(&part[..2], 16),
+        }
+    };
+    Some([channel(0).ok()?, channel(1).ok()?, channel(2).ok()?, 255])
+}